@@ -0,0 +1,302 @@
+use crate::{
+    base64,
+    middleware::Middleware,
+    request::{normalize_path, Request},
+    response::{Response, StatusCode},
+    router::Handler,
+    sha1::sha1,
+};
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Users and password hashes from an htpasswd-style file: one `user:hash`
+/// per line, `#` starts a comment.
+///
+/// Hashes are `{SSHA}` followed by the base64 of `sha1(password + salt)` and
+/// the salt, as `hash_password` makes them. The unsalted `{SHA}` form Apache's
+/// `htpasswd -s` writes is accepted too.
+#[derive(Debug, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+}
+
+impl Htpasswd {
+    pub fn load(path: &Path) -> io::Result<Htpasswd> {
+        Htpasswd::parse(&fs::read_to_string(path)?).map_err(|message| {
+            let message = format!("{}: {message}", path.display());
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    pub fn parse(contents: &str) -> Result<Htpasswd, String> {
+        let mut users = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected `user:hash`", number + 1))?;
+            if !hash.starts_with("{SSHA}") && !hash.starts_with("{SHA}") {
+                return Err(format!("line {}: unsupported hash for {user}", number + 1));
+            }
+            users.insert(user.to_string(), hash.to_string());
+        }
+        Ok(Htpasswd { users })
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(user) else {
+            return false;
+        };
+        let expected = match hash.strip_prefix("{SSHA}") {
+            Some(encoded) => match base64::decode(encoded) {
+                Some(decoded) if decoded.len() > 20 => {
+                    let (digest, salt) = decoded.split_at(20);
+                    return constant_time_eq(&salted_sha1(password, salt), digest);
+                }
+                _ => return false,
+            },
+            None => hash.strip_prefix("{SHA}").and_then(base64::decode),
+        };
+        expected.is_some_and(|digest| constant_time_eq(&sha1(password.as_bytes()), &digest))
+    }
+}
+
+/// An `{SSHA}` hash of `password` for an htpasswd file. The salt should be
+/// random and a few bytes long.
+pub fn hash_password(password: &str, salt: &[u8]) -> String {
+    let mut hashed = salted_sha1(password, salt).to_vec();
+    hashed.extend_from_slice(salt);
+    format!("{{SSHA}}{}", base64::encode(&hashed))
+}
+
+fn salted_sha1(password: &str, salt: &[u8]) -> [u8; 20] {
+    let mut salted = password.as_bytes().to_vec();
+    salted.extend_from_slice(salt);
+    sha1(&salted)
+}
+
+/// Bearer tokens, one per line, `#` starts a comment.
+#[derive(Debug, Default)]
+pub struct Tokens {
+    tokens: Vec<String>,
+}
+
+impl Tokens {
+    pub fn load(path: &Path) -> io::Result<Tokens> {
+        Ok(Tokens::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Tokens {
+        let tokens = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        Tokens { tokens }
+    }
+
+    pub fn verify(&self, token: &str) -> bool {
+        // every token is compared so the time taken doesn't tell which one nearly matched
+        self.tokens.iter().fold(false, |found, t| {
+            constant_time_eq(t.as_bytes(), token.as_bytes()) | found
+        })
+    }
+}
+
+/// Compares without stopping at the first difference, so response times
+/// don't leak how much of a secret an attacker got right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Requires Basic or Bearer credentials for requests below the protected
+/// path prefixes; other requests pass through untouched.
+///
+/// ```no_run
+/// use std::path::Path;
+/// use web_server_final_project::auth::{Auth, Htpasswd};
+///
+/// let auth = Auth::new("admin area")
+///     .with_basic(Htpasswd::load(Path::new("users.htpasswd")).unwrap())
+///     .protect("/admin");
+/// ```
+pub struct Auth {
+    realm: String,
+    basic: Option<Htpasswd>,
+    tokens: Option<Tokens>,
+    prefixes: Vec<String>,
+}
+
+impl Auth {
+    pub fn new(realm: &str) -> Auth {
+        Auth {
+            // the realm goes into a quoted string
+            realm: realm.replace(['"', '\\'], ""),
+            basic: None,
+            tokens: None,
+            prefixes: Vec::new(),
+        }
+    }
+
+    pub fn with_basic(mut self, users: Htpasswd) -> Auth {
+        self.basic = Some(users);
+        self
+    }
+
+    pub fn with_tokens(mut self, tokens: Tokens) -> Auth {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Protects `prefix` and everything below it: `/admin` covers `/admin`
+    /// and `/admin/users`, not `/administrator`.
+    pub fn protect(mut self, prefix: &str) -> Auth {
+        self.prefixes.push(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    fn is_protected(&self, path: &str) -> bool {
+        let path = normalize_path(path);
+        self.prefixes.iter().any(|prefix| {
+            prefix.is_empty() || path == *prefix || path.starts_with(&format!("{prefix}/"))
+        })
+    }
+
+    fn authenticated(&self, authorization: &str) -> bool {
+        let (scheme, credentials) = authorization
+            .trim()
+            .split_once(' ')
+            .unwrap_or((authorization, ""));
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let Some(users) = &self.basic else {
+                return false;
+            };
+            let decoded = base64::decode(credentials).and_then(|d| String::from_utf8(d).ok());
+            match decoded.as_deref().and_then(|d| d.split_once(':')) {
+                Some((user, password)) => users.verify(user, password),
+                None => false,
+            }
+        } else if scheme.eq_ignore_ascii_case("Bearer") {
+            self.tokens.as_ref().is_some_and(|t| t.verify(credentials))
+        } else {
+            false
+        }
+    }
+
+    fn challenge(&self, authorization: Option<&str>) -> Response {
+        let mut response = Response::error(StatusCode::Unauthorized);
+        if self.basic.is_some() {
+            let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm);
+            response.headers.append("WWW-Authenticate", &challenge);
+        }
+        if self.tokens.is_some() {
+            let mut challenge = format!("Bearer realm=\"{}\"", self.realm);
+            // RFC 6750 section 3.1: say why a token that was sent didn't work
+            let sent_token = authorization
+                .and_then(|a| a.trim().get(..7))
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("Bearer "));
+            if sent_token {
+                challenge.push_str(", error=\"invalid_token\"");
+            }
+            response.headers.append("WWW-Authenticate", &challenge);
+        }
+        response
+    }
+}
+
+impl Middleware for Auth {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        if !self.is_protected(&request.path) {
+            return next.handle(request);
+        }
+        match request.header("Authorization") {
+            Some(authorization) if self.authenticated(authorization) => next.handle(request),
+            authorization => self.challenge(authorization),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Chain;
+
+    #[test]
+    fn verifies_passwords_and_tokens() {
+        let users = Htpasswd::parse(&format!(
+            "# admins\nalice:{}\nbob:{{SHA}}{}\n",
+            hash_password("wonderland", b"salt"),
+            base64::encode(&sha1(b"builder")),
+        ))
+        .unwrap();
+        assert!(users.verify("alice", "wonderland"));
+        assert!(!users.verify("alice", "Wonderland"));
+        assert!(users.verify("bob", "builder"));
+        assert!(!users.verify("carol", "wonderland"));
+        assert!(Htpasswd::parse("dave:$apr1$x$y").is_err());
+
+        let tokens = Tokens::parse("# deploy bots\nabc123\n\n");
+        assert!(tokens.verify("abc123"));
+        assert!(!tokens.verify("abc12"));
+    }
+
+    #[test]
+    fn guards_protected_prefixes() {
+        let chain = Chain::new(|_: &mut Request| Response::text("secret")).with(
+            Auth::new("admin")
+                .with_basic(
+                    Htpasswd::parse(&format!("alice:{}", hash_password("pw", b"s1"))).unwrap(),
+                )
+                .with_tokens(Tokens::parse("tok"))
+                .protect("/admin/")
+                .protect("/api/admin"),
+        );
+        let status = |target: &str, authorization: &str| {
+            let raw = format!("GET {target} HTTP/1.1\r\n{authorization}\r\n");
+            let mut request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            chain.handle(&mut request)
+        };
+
+        assert_eq!(status("/administrator", "").status, StatusCode::Ok);
+        assert_eq!(status("/public", "").status, StatusCode::Ok);
+        assert_eq!(status("/api/admin/../users", "").status, StatusCode::Ok);
+        for target in [
+            "/admin",
+            "/admin/users",
+            "/%61dmin/x",
+            "/./admin",
+            "/api/admin",
+            "/api/x/../admin",
+            "/api/%2e%2e/admin",
+        ] {
+            let response = status(target, "");
+            assert_eq!(response.status, StatusCode::Unauthorized, "{target}");
+            let challenges: Vec<&str> = response.headers.get_all("WWW-Authenticate").collect();
+            assert_eq!(
+                challenges,
+                [
+                    "Basic realm=\"admin\", charset=\"UTF-8\"",
+                    "Bearer realm=\"admin\""
+                ]
+            );
+        }
+
+        let basic = format!("Authorization: Basic {}\r\n", base64::encode(b"alice:pw"));
+        assert_eq!(status("/admin", &basic).status, StatusCode::Ok);
+        let wrong = format!("Authorization: Basic {}\r\n", base64::encode(b"alice:nope"));
+        assert_eq!(status("/admin", &wrong).status, StatusCode::Unauthorized);
+        assert_eq!(
+            status("/admin", "Authorization: Bearer tok\r\n").status,
+            StatusCode::Ok
+        );
+        let bad_token = status("/admin", "Authorization: Bearer nope\r\n");
+        assert!(bad_token
+            .headers
+            .get_all("WWW-Authenticate")
+            .any(|c| c.ends_with("error=\"invalid_token\"")));
+    }
+}
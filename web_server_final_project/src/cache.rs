@@ -0,0 +1,303 @@
+use crate::{
+    middleware::Middleware,
+    request::{Method, Request},
+    response::Response,
+    router::Handler,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A file's contents along with the validators clients use to revalidate it.
+#[derive(Debug)]
+pub struct CachedFile {
+    pub contents: Vec<u8>,
+    pub modified: SystemTime,
+    pub etag: String,
+}
+
+impl CachedFile {
+    pub fn read(path: &Path) -> io::Result<CachedFile> {
+        let mut file = File::open(path)?;
+        // the metadata of the open file can't belong to a different file than the contents
+        let metadata = file.metadata()?;
+        let mut contents = Vec::with_capacity(metadata.len() as usize);
+        file.read_to_end(&mut contents)?;
+
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        Ok(CachedFile {
+            etag: etag(metadata.len(), modified),
+            contents,
+            modified,
+        })
+    }
+}
+
+/// A strong validator from the size and modification time, like most servers
+/// use; hashing the contents would mean reading every file on every request.
+pub fn etag(len: u64, modified: SystemTime) -> String {
+    let nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("\"{len:x}-{nanos:x}\"")
+}
+
+/// Whether the copy the client already has is current, judging by its
+/// `If-None-Match` or, without one, its `If-Modified-Since`. Only applies to
+/// `GET` and `HEAD`.
+pub fn not_modified(request: &Request, etag: &str, modified: SystemTime) -> bool {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return false;
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        return if_none_match.trim() == "*"
+            || if_none_match
+                .split(',')
+                .any(|tag| weak_match(tag.trim(), etag));
+    }
+
+    match request
+        .header("If-Modified-Since")
+        .and_then(crate::date::parse_http_date)
+    {
+        // Last-Modified only has whole seconds
+        Some(since) => seconds(modified) <= seconds(since),
+        None => false,
+    }
+}
+
+/// Compares entity tags ignoring the weak `W/` prefix and the suffix the
+/// compression middleware adds for the encoded variant.
+fn weak_match(a: &str, b: &str) -> bool {
+    fn opaque(tag: &str) -> &str {
+        let tag = tag.strip_prefix("W/").unwrap_or(tag);
+        ["-gzip\"", "-deflate\""]
+            .iter()
+            .find_map(|suffix| tag.strip_suffix(suffix))
+            .unwrap_or(tag.strip_suffix('"').unwrap_or(tag))
+    }
+    opaque(a) == opaque(b)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Keeps recently served files in memory, up to `max_size` bytes in total,
+/// dropping the least recently used ones first. Entries are checked against
+/// the file's size and modification time on every lookup, so edits show up
+/// right away.
+pub struct FileCache {
+    max_size: u64,
+    max_file_size: u64,
+    state: Mutex<CacheState>,
+}
+
+struct CacheState {
+    entries: HashMap<PathBuf, Entry>,
+    size: u64,
+    /// Counts lookups; an entry remembers the count at its last use.
+    clock: u64,
+}
+
+struct Entry {
+    file: Arc<CachedFile>,
+    last_used: u64,
+}
+
+impl FileCache {
+    /// Files larger than `max_file_size` are read every time.
+    pub fn new(max_size: u64, max_file_size: u64) -> FileCache {
+        FileCache {
+            max_size,
+            max_file_size: max_file_size.min(max_size),
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                size: 0,
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> io::Result<Arc<CachedFile>> {
+        let metadata = fs::metadata(path)?;
+        let current = etag(metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH));
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(path) {
+                if entry.file.etag == current {
+                    entry.last_used = clock;
+                    return Ok(Arc::clone(&entry.file));
+                }
+            }
+        }
+
+        // read without holding the lock so other files can be served meanwhile
+        let file = Arc::new(CachedFile::read(path)?);
+        let size = file.contents.len() as u64;
+        if size > self.max_file_size {
+            return Ok(file);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let clock = state.clock;
+        let entry = Entry {
+            file: Arc::clone(&file),
+            last_used: clock,
+        };
+        if let Some(old) = state.entries.insert(path.to_path_buf(), entry) {
+            state.size -= old.file.contents.len() as u64;
+        }
+        state.size += size;
+
+        while state.size > self.max_size {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            let Some(entry) = oldest.and_then(|path| state.entries.remove(&path)) else {
+                break;
+            };
+            state.size -= entry.file.contents.len() as u64;
+        }
+        Ok(file)
+    }
+
+    /// Files larger than this are not kept.
+    pub fn max_file_size(&self) -> u64 {
+        self.max_file_size
+    }
+
+    /// Bytes currently held.
+    pub fn size(&self) -> u64 {
+        self.state.lock().unwrap().size
+    }
+}
+
+/// Sets `Cache-Control` on successful responses that don't have one, by the
+/// longest matching path prefix.
+#[derive(Default)]
+pub struct CacheControl {
+    rules: Vec<(String, String)>,
+}
+
+impl CacheControl {
+    pub fn new() -> CacheControl {
+        CacheControl::default()
+    }
+
+    /// `rule("/assets/", "public, max-age=86400")`
+    pub fn rule(mut self, prefix: &str, value: &str) -> CacheControl {
+        self.rules.push((prefix.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl Middleware for CacheControl {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let path = request.path.clone();
+        let mut response = next.handle(request);
+
+        let status = response.status.as_u16();
+        let cacheable = (200..300).contains(&status) || status == 304;
+        if !cacheable || response.headers.contains("Cache-Control") {
+            return response;
+        }
+        let rule = self
+            .rules
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len());
+        if let Some((_, value)) = rule {
+            response.headers.insert("Cache-Control", value);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::format_http_date;
+    use std::time::Duration;
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn evaluates_conditional_headers() {
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        let tag = etag(10, modified);
+
+        let matching = format!("GET / HTTP/1.1\r\nIf-None-Match: \"x\", {tag}\r\n\r\n");
+        assert!(not_modified(&request(&matching), &tag, modified));
+        let gzipped = format!(
+            "GET / HTTP/1.1\r\nIf-None-Match: W/{}-gzip\"\r\n\r\n",
+            &tag[..tag.len() - 1]
+        );
+        assert!(not_modified(&request(&gzipped), &tag, modified));
+        assert!(!not_modified(
+            &request("GET / HTTP/1.1\r\nIf-None-Match: \"x\"\r\n\r\n"),
+            &tag,
+            modified
+        ));
+
+        let since = format!(
+            "GET / HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+            format_http_date(modified)
+        );
+        assert!(not_modified(&request(&since), &tag, modified));
+        let later = modified + Duration::from_secs(1);
+        assert!(!not_modified(&request(&since), &etag(10, later), later));
+        // dates that can't be represented are ignored
+        let far =
+            "GET / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 99999999999999 08:49:37 GMT\r\n\r\n";
+        assert!(!not_modified(&request(far), &tag, modified));
+
+        let post = format!("POST / HTTP/1.1\r\nIf-None-Match: {tag}\r\n\r\n");
+        assert!(!not_modified(&request(&post), &tag, modified));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = std::env::temp_dir().join(format!("web_server_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c", "big"] {
+            let size = if name == "big" { 100 } else { 10 };
+            fs::write(dir.join(name), vec![b'x'; size]).unwrap();
+        }
+
+        let cache = FileCache::new(25, 50);
+        cache.get(&dir.join("a")).unwrap();
+        cache.get(&dir.join("b")).unwrap();
+        cache.get(&dir.join("a")).unwrap();
+        cache.get(&dir.join("c")).unwrap();
+        assert_eq!(cache.size(), 20);
+        {
+            let state = cache.state.lock().unwrap();
+            assert!(state.entries.contains_key(&dir.join("a")));
+            assert!(!state.entries.contains_key(&dir.join("b")));
+        }
+
+        assert_eq!(cache.get(&dir.join("big")).unwrap().contents.len(), 100);
+        assert_eq!(cache.size(), 20);
+
+        fs::write(dir.join("a"), b"changed").unwrap();
+        assert_eq!(cache.get(&dir.join("a")).unwrap().contents, b"changed");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
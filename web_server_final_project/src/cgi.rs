@@ -0,0 +1,351 @@
+use crate::{
+    log,
+    log::Level,
+    request::{percent_decode, Request},
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const SERVER_SOFTWARE: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Runs a CGI/1.1 script (RFC 3875) for each request.
+///
+/// The request is described to the script in environment variables and its
+/// body is piped to the script's stdin. The script answers on stdout with a
+/// header block, a blank line and the body; `Status:` sets the status code.
+/// Lines on stderr go to the server log.
+///
+/// When mounted on a wildcard route (`/cgi-bin/report/*`) the captured part
+/// of the path is passed as `PATH_INFO`.
+pub struct Cgi {
+    script: PathBuf,
+    timeout: Duration,
+}
+
+impl Cgi {
+    pub fn new(script: impl Into<PathBuf>) -> Cgi {
+        Cgi {
+            script: script.into(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// How long the script may run before it is killed and the client gets a
+    /// 504. 30 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    fn command(&self, request: &Request) -> Command {
+        let mut command = Command::new(&self.script);
+        // the script sees only what CGI defines, not the server's own environment
+        command.env_clear();
+        if let Some(path) = env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        if let Some(dir) = self.script.parent().filter(|d| !d.as_os_str().is_empty()) {
+            command.current_dir(dir);
+        }
+        for (name, value) in environment(request) {
+            command.env(name, value);
+        }
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+
+    /// Waits for `child` to exit, killing it once the timeout is up. Returns
+    /// whether it exited in time.
+    fn wait(&self, child: &mut Child) -> bool {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    if !status.success() {
+                        log!(
+                            Level::Warn,
+                            "CGI script {} exited with {status}",
+                            self.script.display()
+                        );
+                    }
+                    return true;
+                }
+                Ok(None) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                _ => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, request: &mut Request) -> Response {
+        let mut child = match self.command(request).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                log!(
+                    Level::Error,
+                    "can't run CGI script {}: {e}",
+                    self.script.display()
+                );
+                return Response::error(StatusCode::InternalServerError);
+            }
+        };
+
+        // all three pipes are served at once, a script may not read its whole
+        // body before it starts writing
+        let stdin = child.stdin.take().map(|mut stdin| {
+            let body = std::mem::take(&mut request.body);
+            thread::spawn(move || {
+                // the script may not care about the body and exit early
+                let _ = stdin.write_all(&body);
+            })
+        });
+        let stdout = child.stdout.take().map(|mut stdout| {
+            thread::spawn(move || {
+                let mut output = Vec::new();
+                let _ = stdout.read_to_end(&mut output);
+                output
+            })
+        });
+        let script = self.script.display().to_string();
+        let stderr = child.stderr.take().map(|stderr| {
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    log!(Level::Warn, "{script}: {line}");
+                }
+            })
+        });
+
+        if !self.wait(&mut child) {
+            // the threads are left to finish on their own: a process the
+            // script started may still hold the pipes open
+            log!(
+                Level::Warn,
+                "CGI script {} timed out after {:?}",
+                self.script.display(),
+                self.timeout
+            );
+            return Response::error(StatusCode::GatewayTimeout);
+        }
+        let output = stdout.and_then(|t| t.join().ok()).unwrap_or_default();
+        for thread in [stdin, stderr].into_iter().flatten() {
+            let _ = thread.join();
+        }
+
+        parse_output(&output).unwrap_or_else(|message| {
+            log!(
+                Level::Warn,
+                "CGI script {}: {message}",
+                self.script.display()
+            );
+            Response::error(StatusCode::BadGateway)
+        })
+    }
+}
+
+/// The meta-variables of RFC 3875 section 4.1, plus `HTTP_*` ones for the
+/// request headers.
+fn environment(request: &Request) -> Vec<(String, String)> {
+    let path_info = request
+        .param("*")
+        .map(|info| format!("/{info}"))
+        .unwrap_or_default();
+    let script_name = request
+        .path
+        .strip_suffix(path_info.as_str())
+        .unwrap_or(&request.path);
+    let host = request.header("Host").unwrap_or("");
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => (name, port),
+        _ => (host, "80"),
+    };
+
+    let mut vars = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+        ("SERVER_SOFTWARE", SERVER_SOFTWARE.to_string()),
+        ("SERVER_PROTOCOL", request.version.as_str().to_string()),
+        ("SERVER_NAME", server_name.to_string()),
+        ("SERVER_PORT", server_port.to_string()),
+        ("REQUEST_METHOD", request.method.as_str().to_string()),
+        ("REQUEST_URI", request.target()),
+        ("SCRIPT_NAME", script_name.to_string()),
+        // unlike the other paths, PATH_INFO is decoded, RFC 3875 section 4.1.5
+        (
+            "PATH_INFO",
+            percent_decode(&path_info).unwrap_or_else(|| path_info.clone()),
+        ),
+        ("QUERY_STRING", request.query.clone().unwrap_or_default()),
+    ];
+    if let Some(addr) = request.remote_addr {
+        vars.push(("REMOTE_ADDR", addr.ip().to_string()));
+        vars.push(("REMOTE_PORT", addr.port().to_string()));
+    }
+    if !request.body.is_empty() || request.header("Content-Length").is_some() {
+        vars.push(("CONTENT_LENGTH", request.body.len().to_string()));
+    }
+    if let Some(content_type) = request.header("Content-Type") {
+        vars.push(("CONTENT_TYPE", content_type.to_string()));
+    }
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+
+    for (name, value) in request.headers.iter() {
+        let name = name.to_ascii_uppercase().replace('-', "_");
+        // CONTENT_* are set above; credentials stay with the server; and
+        // `Proxy` would become HTTP_PROXY, which many tools take as their proxy
+        if matches!(
+            name.as_str(),
+            "CONTENT_LENGTH" | "CONTENT_TYPE" | "AUTHORIZATION" | "PROXY"
+        ) {
+            continue;
+        }
+        let name = format!("HTTP_{name}");
+        // repeated headers are joined, as they would be on one line
+        match vars.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, joined)) => {
+                joined.push_str(", ");
+                joined.push_str(value);
+            }
+            None => vars.push((name, value.to_string())),
+        }
+    }
+    vars
+}
+
+/// Turns a script's output into a response: the header block up to the
+/// first blank line, then the body.
+fn parse_output(output: &[u8]) -> Result<Response, String> {
+    let (head, body) = ["\r\n\r\n", "\n\n"]
+        .iter()
+        .filter_map(|blank| {
+            let at = output
+                .windows(blank.len())
+                .position(|w| w == blank.as_bytes())?;
+            Some((at, at + blank.len()))
+        })
+        .min()
+        .map(|(end, body)| (&output[..end], &output[body..]))
+        .ok_or("no blank line after the headers")?;
+    let head = std::str::from_utf8(head).map_err(|_| "headers are not UTF-8")?;
+
+    let mut response = Response::new(StatusCode::Ok).with_body(body);
+    let mut status = None;
+    for line in head.lines() {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed header line `{line}`"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Status") {
+            // interim responses aren't final answers, and a body on a status
+            // that can't have one would throw the client off the framing
+            let code = value.split(' ').next().and_then(|c| c.parse().ok());
+            match code
+                .filter(|c| (200..=599).contains(c))
+                .map(StatusCode::from_u16)
+            {
+                Some(code) if code.allows_body() || body.is_empty() => status = Some(code),
+                _ => return Err(format!("invalid status `{value}`")),
+            }
+        } else {
+            response.headers.append(name.trim(), value);
+        }
+    }
+    response.status = match status {
+        Some(status) => status,
+        // a Location without a Status is a redirect, RFC 3875 section 6.2.3
+        None if response.headers.contains("Location") => StatusCode::Found,
+        None => StatusCode::Ok,
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_script_output() {
+        let response =
+            parse_output(b"Status: 404 Nope\nContent-Type: text/plain\n\nmissing").unwrap();
+        assert_eq!(response.status, StatusCode::NotFound);
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain"));
+        assert_eq!(response.body.as_bytes(), Some(&b"missing"[..]));
+
+        let redirect = parse_output(b"Location: /elsewhere\r\n\r\n").unwrap();
+        assert_eq!(redirect.status, StatusCode::Found);
+
+        assert!(parse_output(b"Content-Type: text/plain").is_err());
+        assert!(parse_output(b"Status: many\n\n").is_err());
+        for status in ["101 Switching Protocols", "199", "600", "204 No Content"] {
+            let output = format!("Status: {status}\n\nbody");
+            assert!(parse_output(output.as_bytes()).is_err(), "{status}");
+        }
+        let empty = parse_output(b"Status: 204 No Content\n\n").unwrap();
+        assert_eq!(empty.status, StatusCode::NoContent);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_scripts_with_the_cgi_environment() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = env::temp_dir().join(format!("web_server_cgi_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("echo.sh");
+        fs::write(
+            &script,
+            "#!/bin/sh\necho 'Content-Type: text/plain'\necho\n\
+             echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING $HTTP_X_TEST\"\n\
+             echo oops >&2\ncat\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let raw = "POST /cgi/echo/extra/path?a=1 HTTP/1.1\r\nX-Test: yes\r\nContent-Length: 4\r\n\r\nbody";
+        let mut request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+        request.params = vec![("*".to_string(), "extra/path".to_string())];
+        let response = Cgi::new(&script).handle(&mut request);
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(
+            response.body.as_bytes(),
+            Some(&b"POST /cgi/echo /extra/path a=1 yes\nbody"[..])
+        );
+
+        let script = dir.join("slow.sh");
+        fs::write(&script, "#!/bin/sh\nsleep 5\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let slow = Cgi::new(&script).with_timeout(Duration::from_millis(100));
+        let started = Instant::now();
+        assert_eq!(slow.handle(&mut request).status, StatusCode::GatewayTimeout);
+        assert!(started.elapsed() < Duration::from_secs(4));
+
+        let missing = Cgi::new(dir.join("missing.sh")).handle(&mut request);
+        assert_eq!(missing.status, StatusCode::InternalServerError);
+        // a script that fails before it prints its headers
+        let script = dir.join("broken.sh");
+        fs::write(&script, "#!/bin/sh\necho 'no such command' >&2\nexit 3\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let broken = Cgi::new(&script).handle(&mut request);
+        assert_eq!(broken.status, StatusCode::BadGateway);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    request::{self, invalid_data, ChunkedReader, Headers, Limits, Method, ParseError},
+    response::StatusCode,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::TcpStream,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// A small blocking HTTP/1.1 client, enough for tests and tooling.
+///
+/// Connections are kept alive and reused for later requests to the same
+/// host and port. Only `http://` URLs are supported.
+///
+/// ```no_run
+/// use web_server_final_project::client::Client;
+///
+/// let client = Client::new();
+/// let response = client.get("http://127.0.0.1:7878/").send().unwrap();
+/// println!("{} {}", response.status, response.text());
+/// ```
+pub struct Client {
+    timeout: Duration,
+    /// Idle connections by `host:port`.
+    idle: Mutex<HashMap<String, Vec<BufReader<TcpStream>>>>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            timeout: Duration::from_secs(30),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long connecting, and then each read or write, may take. 30
+    /// seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            url: url.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Get, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Post, url)
+    }
+
+    fn connect(&self, authority: &str) -> io::Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect(authority)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(BufReader::new(stream))
+    }
+
+    fn take_idle(&self, authority: &str) -> Option<BufReader<TcpStream>> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.get_mut(authority)?.pop()
+    }
+
+    fn put_idle(&self, authority: &str, connection: BufReader<TcpStream>) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        idle.entry(authority.to_string())
+            .or_default()
+            .push(connection);
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    method: Method,
+    url: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl RequestBuilder<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn send(self) -> io::Result<ClientResponse> {
+        let (authority, target) = split_url(&self.url)?;
+        let authority = authority.as_str();
+        let mut head = format!("{} {target} HTTP/1.1\r\n", self.method);
+        if !self.headers.contains("Host") {
+            head.push_str(&format!("Host: {authority}\r\n"));
+        }
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if !self.body.is_empty() || matches!(self.method, Method::Post | Method::Put) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        // a kept-alive connection may have been closed by the server in the
+        // meantime; only then is the request sent again, on a new connection,
+        // and only if sending it twice does no harm
+        if let Some(mut connection) = self.client.take_idle(authority) {
+            match self.exchange(&mut connection, &head) {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.client.put_idle(authority, connection);
+                    }
+                    return Ok(response);
+                }
+                Err(e) if !is_stale(&e) || !is_idempotent(self.method) => return Err(e),
+                Err(_) => {}
+            }
+        }
+        let mut connection = self.client.connect(authority)?;
+        let (response, reusable) = self.exchange(&mut connection, &head)?;
+        if reusable {
+            self.client.put_idle(authority, connection);
+        }
+        Ok(response)
+    }
+
+    /// Sends the request and reads the response; also says whether the
+    /// connection can take another request.
+    fn exchange(
+        &self,
+        connection: &mut BufReader<TcpStream>,
+        head: &str,
+    ) -> io::Result<(ClientResponse, bool)> {
+        let mut w = BufWriter::new(connection.get_ref());
+        w.write_all(head.as_bytes())?;
+        w.write_all(&self.body)?;
+        w.flush()?;
+        drop(w);
+
+        let (status, headers) = read_head(connection)?;
+        let mut body = Vec::new();
+        let mut reusable = !headers.has_token("Connection", "close");
+        if self.method != Method::Head && status.allows_body() {
+            if headers.has_token("Transfer-Encoding", "chunked") {
+                ChunkedReader::new(&mut *connection).read_to_end(&mut body)?;
+            } else if let Some(length) = headers.get("Content-Length") {
+                let length: u64 = length.trim().parse().map_err(invalid_data)?;
+                let read = connection.by_ref().take(length).read_to_end(&mut body)?;
+                if (read as u64) < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            } else {
+                // the server marks the end by closing the connection
+                connection.read_to_end(&mut body)?;
+                reusable = false;
+            }
+        }
+        Ok((
+            ClientResponse {
+                status,
+                headers,
+                body,
+            },
+            reusable,
+        ))
+    }
+}
+
+/// A response read in full.
+#[derive(Debug)]
+pub struct ClientResponse {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body as text, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Reads a response's status line and headers, skipping interim `1xx`
+/// responses.
+pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> io::Result<(StatusCode, Headers)> {
+    let limits = Limits::default();
+    loop {
+        let line = request::read_line(reader, limits.max_line_len, ParseError::BadRequestLine)
+            .map_err(|e| match e {
+                ParseError::Io(e) => e,
+                ParseError::UnexpectedEof => io::ErrorKind::UnexpectedEof.into(),
+                e => invalid_data(e),
+            })?;
+        let status = line
+            .strip_prefix("HTTP/1.")
+            .and_then(|rest| rest.get(2..5))
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| invalid_data("malformed status line"))?;
+        let headers = request::read_headers(reader, &limits).map_err(invalid_data)?;
+        if !(100..200).contains(&status) {
+            return Ok((StatusCode::from_u16(status), headers));
+        }
+    }
+}
+
+/// Splits `http://host:port/path?query` into the authority, with the port
+/// defaulting to 80, and the request target.
+fn split_url(url: &str) -> io::Result<(String, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http:// URLs are supported",
+        )
+    })?;
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(at) if rest[at..].starts_with('/') => (&rest[..at], rest[at..].to_string()),
+        // `http://host?query` asks for the root with that query
+        Some(at) => (&rest[..at], format!("/{}", &rest[at..])),
+        None => (rest, "/".to_string()),
+    };
+    if authority.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "URL without a host",
+        ));
+    }
+    // the last colon is part of an IPv6 address when a `]` follows it
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'));
+    let authority = if has_port {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    Ok((authority, target))
+}
+
+/// Whether a request may be sent again when it is unclear whether the
+/// server got it, see RFC 9110 section 9.2.2.
+fn is_idempotent(method: Method) -> bool {
+    matches!(
+        method,
+        Method::Get | Method::Head | Method::Put | Method::Delete | Method::Options
+    )
+}
+
+/// Whether an error on a reused connection means the server had already
+/// closed it.
+fn is_stale(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_urls() {
+        let split = |url| split_url(url).unwrap();
+        assert_eq!(
+            split("http://127.0.0.1:8080/a/b?c=d"),
+            ("127.0.0.1:8080".into(), "/a/b?c=d".into())
+        );
+        assert_eq!(
+            split("http://example.com"),
+            ("example.com:80".into(), "/".into())
+        );
+        assert_eq!(
+            split("http://example.com/"),
+            ("example.com:80".into(), "/".into())
+        );
+        assert_eq!(
+            split("http://example.com?q=1"),
+            ("example.com:80".into(), "/?q=1".into())
+        );
+        assert_eq!(split("http://[::1]/").0, "[::1]:80");
+        assert_eq!(split("http://[::1]:8080/").0, "[::1]:8080");
+        assert!(split_url("https://example.com/").is_err());
+        assert!(split_url("http:///path").is_err());
+    }
+}
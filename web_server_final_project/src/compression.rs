@@ -0,0 +1,501 @@
+use crate::{
+    middleware::Middleware,
+    request::{Headers, Request},
+    response::{Body, Response, StatusCode},
+    router::Handler,
+};
+use std::io::Read;
+
+/// Content codings we can produce, in order of preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coding {
+    Gzip,
+    Deflate,
+}
+
+impl Coding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Coding::Gzip => gzip(data),
+            Coding::Deflate => zlib(data),
+        }
+    }
+}
+
+/// The quality value the client gave `coding` in `Accept-Encoding`, 0 if it
+/// doesn't accept it. A `*` entry covers codings not listed by name.
+pub fn accepted_quality(headers: &Headers, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for entry in headers
+        .get_all("Accept-Encoding")
+        .flat_map(|v| v.split(','))
+    {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+/// Picks the coding the client likes best, preferring gzip on a tie.
+pub fn negotiate(headers: &Headers) -> Option<Coding> {
+    let gzip = accepted_quality(headers, "gzip");
+    let deflate = accepted_quality(headers, "deflate");
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Coding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Coding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Media types worth compressing: text and text-based formats. Images,
+/// archives and video are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/x-icon"
+        )
+}
+
+/// Compresses text-like responses the client accepts a coding for.
+pub struct Compression {
+    /// Smaller bodies are sent as is, compressing them gains nothing.
+    pub min_size: u64,
+    /// Bodies up to this size are read into memory and compressed anew on
+    /// every request; larger ones are sent as is. Big static files are best
+    /// served from a precompressed `.gz` sibling instead.
+    pub max_size: u64,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            min_size: 1024,
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let mut response = next.handle(request);
+
+        let compressible = response.status.allows_body()
+            && !response.is_length_only()
+            && response
+                .headers
+                .get("Content-Type")
+                .is_some_and(is_compressible);
+        if !compressible {
+            return response;
+        }
+        // caches must keep the compressed and plain variants apart
+        if !response.headers.has_token("Vary", "Accept-Encoding") {
+            response.headers.append("Vary", "Accept-Encoding");
+        }
+
+        // partial content refers to byte offsets of the uncompressed representation
+        if response.status == StatusCode::PartialContent
+            || response.headers.contains("Content-Encoding")
+        {
+            return response;
+        }
+        let Some(coding) = negotiate(&request.headers) else {
+            return response;
+        };
+        let length = match response.body.len() {
+            Some(length) if (self.min_size..=self.max_size).contains(&length) => length,
+            _ => return response,
+        };
+
+        let body = std::mem::replace(&mut response.body, Body::Bytes(Vec::new()));
+        let data = match body {
+            Body::Bytes(bytes) => bytes,
+            Body::Reader { reader, .. } => {
+                let mut data = Vec::with_capacity(length as usize);
+                if reader.take(length).read_to_end(&mut data).is_err() {
+                    return Response::error(StatusCode::InternalServerError);
+                }
+                data
+            }
+        };
+
+        response.headers.insert("Content-Encoding", coding.as_str());
+        if let Some(etag) = response.headers.get("ETag") {
+            // the compressed bytes are a different representation with their own validator
+            let etag = match etag.strip_suffix('"') {
+                Some(open) => format!("{open}-{}\"", coding.as_str()),
+                None => etag.to_string(),
+            };
+            response.headers.insert("ETag", &etag);
+        }
+        response.with_body(coding.encode(&data))
+    }
+}
+
+/// Wraps a DEFLATE stream in the gzip format (RFC 1952).
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // no file name or modification time; 255 = unknown operating system
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Wraps a DEFLATE stream in the zlib format (RFC 1950), which is what the
+/// `deflate` content coding means in HTTP.
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65_521;
+        b %= 65_521;
+    }
+    (b << 16) | a
+}
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// (base length, extra bits) for length codes 257..=285, RFC 1951 section 3.2.5.
+#[rustfmt::skip]
+const LENGTH_CODES: [(u16, u8); 29] = [
+    (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0),
+    (11, 1), (13, 1), (15, 1), (17, 1), (19, 2), (23, 2), (27, 2), (31, 2),
+    (35, 3), (43, 3), (51, 3), (59, 3), (67, 4), (83, 4), (99, 4), (115, 4),
+    (131, 5), (163, 5), (195, 5), (227, 5), (258, 0),
+];
+
+/// (base distance, extra bits) for distance codes 0..=29.
+#[rustfmt::skip]
+const DISTANCE_CODES: [(u16, u8); 30] = [
+    (1, 0), (2, 0), (3, 0), (4, 0), (5, 1), (7, 1), (9, 2), (13, 2),
+    (17, 3), (25, 3), (33, 4), (49, 4), (65, 5), (97, 5), (129, 6), (193, 6),
+    (257, 7), (385, 7), (513, 8), (769, 8), (1025, 9), (1537, 9), (2049, 10), (3073, 10),
+    (4097, 11), (6145, 11), (8193, 12), (12289, 12), (16385, 13), (24577, 13),
+];
+
+/// Packs bits least significant first, as DEFLATE streams are laid out.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, count: u32) {
+        self.buffer |= u64::from(bits) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most significant bit first, so they are
+    /// reversed before going into the stream.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code.
+fn write_symbol(bits: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_CODES
+        .iter()
+        .rposition(|&(base, _)| usize::from(base) <= length)
+        .unwrap();
+    let (base, extra) = LENGTH_CODES[code];
+    write_symbol(bits, 257 + code as u32);
+    bits.write((length - usize::from(base)) as u32, u32::from(extra));
+
+    let code = DISTANCE_CODES
+        .iter()
+        .rposition(|&(base, _)| usize::from(base) <= distance)
+        .unwrap();
+    let (base, extra) = DISTANCE_CODES[code];
+    bits.write_code(code as u32, 5);
+    bits.write((distance - usize::from(base)) as u32, u32::from(extra));
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value =
+        u32::from(data[pos]) << 16 | u32::from(data[pos + 1]) << 8 | u32::from(data[pos + 2]);
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` into a raw DEFLATE stream (RFC 1951): one block using
+/// the fixed Huffman codes, with matches found through hash chains. Not as
+/// tight as zlib, but far simpler and good enough for text.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        buffer: 0,
+        count: 0,
+    };
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    bits.write(0b011, 3);
+
+    // most recent position for each hash, and the previous one with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |pos: usize, head: &mut [usize], prev: &mut [usize]| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(data, pos);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(data, pos)];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, pos - candidate);
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // a slot overwritten by a newer position would point forwards
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        let (length, distance) = best;
+        if length >= MIN_MATCH {
+            write_match(&mut bits, length, distance);
+            for p in pos..pos + length {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += length;
+        } else {
+            write_symbol(&mut bits, u32::from(data[pos]));
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    // end of block
+    write_symbol(&mut bits, 256);
+    bits.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decoder for the subset `deflate` produces: one final fixed-Huffman block.
+    fn inflate_fixed(stream: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut bit = |n: u32| {
+            let mut value = 0;
+            for i in 0..n {
+                let b = (stream[pos / 8] >> (pos % 8)) & 1;
+                value |= u32::from(b) << i;
+                pos += 1;
+            }
+            value
+        };
+        assert_eq!(bit(3), 0b011);
+
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            // fixed codes are read most significant bit first
+            let mut code = 0;
+            for _ in 0..7 {
+                code = code << 1 | bit(1);
+            }
+            let symbol = if code <= 0x17 {
+                code + 256
+            } else {
+                code = code << 1 | bit(1);
+                if (0x30..=0xbf).contains(&code) {
+                    code - 0x30
+                } else if (0xc0..=0xc7).contains(&code) {
+                    code - 0xc0 + 280
+                } else {
+                    (code << 1 | bit(1)) - 0x190 + 144
+                }
+            };
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => return out,
+                _ => {
+                    let (base, extra) = LENGTH_CODES[symbol as usize - 257];
+                    let length = usize::from(base) + bit(u32::from(extra)) as usize;
+                    let mut code = 0;
+                    for _ in 0..5 {
+                        code = code << 1 | bit(1);
+                    }
+                    let (base, extra) = DISTANCE_CODES[code as usize];
+                    let distance = usize::from(base) + bit(u32::from(extra)) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn deflate_round_trips() {
+        let text = "<p>Hello, hello, hello! Rust is fast, Rust is safe.</p>\n".repeat(200);
+        let binary: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        for data in [text.as_bytes(), &binary, b"", b"a"] {
+            let compressed = deflate(data);
+            assert_eq!(inflate_fixed(&compressed), data);
+        }
+        assert!(deflate(text.as_bytes()).len() < text.len() / 10);
+    }
+
+    #[test]
+    fn negotiates_codings() {
+        let mut headers = Headers::new();
+        headers.append("Accept-Encoding", "deflate, gzip;q=0.5");
+        assert_eq!(negotiate(&headers), Some(Coding::Deflate));
+
+        headers.insert("Accept-Encoding", "br, gzip");
+        assert_eq!(negotiate(&headers), Some(Coding::Gzip));
+
+        headers.insert("Accept-Encoding", "gzip;q=0, *;q=0.1");
+        assert_eq!(negotiate(&headers), Some(Coding::Deflate));
+
+        headers.insert("Accept-Encoding", "identity");
+        assert_eq!(negotiate(&headers), None);
+    }
+
+    #[test]
+    fn compresses_large_text_only() {
+        let chain = crate::middleware::Chain::new(|req: &mut Request| match req.path.as_str() {
+            "/small" => Response::text("tiny"),
+            "/png" => Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096]),
+            _ => Response::text("x".repeat(4096)),
+        })
+        .with(Compression::default());
+        let get = |path: &str| {
+            let raw = format!("GET {path} HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+            chain.handle(&mut Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap())
+        };
+
+        let large = get("/large");
+        assert_eq!(large.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(large.headers.get("Vary"), Some("Accept-Encoding"));
+        let body = large.body.as_bytes().unwrap();
+        assert_eq!(&body[..2], &[0x1f, 0x8b]);
+        assert_eq!(
+            inflate_fixed(&body[10..body.len() - 8]),
+            "x".repeat(4096).as_bytes()
+        );
+
+        let small = get("/small");
+        assert_eq!(small.headers.get("Content-Encoding"), None);
+        assert_eq!(small.headers.get("Vary"), Some("Accept-Encoding"));
+
+        let png = get("/png");
+        assert_eq!(png.headers.get("Content-Encoding"), None);
+        assert_eq!(png.headers.get("Vary"), None);
+    }
+}
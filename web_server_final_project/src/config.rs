@@ -0,0 +1,470 @@
+use crate::{
+    log::{Level, LogFormat},
+    ratelimit::Rate,
+    request::Limits,
+    server::ConnectionSettings,
+};
+use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+pub const USAGE: &str = "\
+Usage: web_server_final_project [OPTIONS]
+
+Options:
+  --config FILE           read settings from FILE before applying the flags below
+  --bind ADDRS            comma separated addresses to listen on [127.0.0.1:7878]
+  --tls-bind ADDRS        addresses to listen on for HTTPS, needs the `tls` feature
+  --tls-cert FILE         PEM certificate chain for HTTPS
+  --tls-key FILE          PEM private key for HTTPS
+  --workers N             worker threads [4]
+  --root DIR              document root for static files [public]
+  --cgi SCRIPT            `PATH FILE`, run the CGI script FILE for PATH; may be repeated
+  --cgi-timeout SECS      how long a CGI script may run [30]
+  --listings DIR          list directories without index.html under root DIR; may be repeated
+  --proxy ROUTE           `PREFIX HOST:PORT,...`, forward PREFIX upstream; may be repeated
+  --protect PREFIX        require a login below PREFIX; may be repeated
+  --auth-users FILE       `user:{SSHA}hash` lines for Basic authentication
+  --auth-tokens FILE      bearer tokens, one per line
+  --auth-realm NAME       realm named in the login challenge [restricted]
+  --vhost SITE            `HOST DIR`, serve HOST (or `*.domain`) from DIR; may be repeated
+  --idle-timeout SECS     how long kept-alive connections may sit idle [5]
+  --request-timeout SECS  how long a client may take to send a request [10]
+  --grace-period SECS     how long to drain connections on shutdown [30]
+  --max-requests N        requests per connection before closing it [100]
+  --max-body BYTES        largest accepted request body [1048576]
+  --rate-limit RATE       requests per client IP, e.g. `10/s` or `300/m` [unlimited]
+  --route-limit RULE      `PREFIX RATE`, a separate limit below PREFIX; may be repeated
+  --max-conns-per-ip N    open connections per client IP, 0 for no cap [0]
+  --compression on|off    gzip/deflate text responses for clients that accept it [on]
+  --compress-min BYTES    smallest response body worth compressing [1024]
+  --cache-control RULE    `PREFIX VALUE`, e.g. `/assets/ max-age=86400`; may be repeated
+  --file-cache BYTES      memory for caching static files, 0 to disable [16777216]
+  --file-cache-max BYTES  largest file kept in the cache [1048576]
+  --access-log DEST       `stdout`, `off` or a file path [stdout]
+  --log-format FORMAT     common, combined or json [combined]
+  --log-level LEVEL       error, warn, info or debug [info]
+  -h, --help              print this help
+
+The config file holds one `key = value` per line, using the option names
+without the leading dashes (`idle-timeout` or `idle_timeout`); lines starting
+with `#` are comments.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Off,
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: Vec<SocketAddr>,
+    pub tls_bind: Vec<SocketAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub workers: usize,
+    pub document_root: PathBuf,
+    pub idle_timeout: Duration,
+    pub request_timeout: Duration,
+    pub grace_period: Duration,
+    pub max_requests: usize,
+    pub max_body_len: usize,
+    pub rate_limit: Option<Rate>,
+    /// (path prefix, rate) pairs.
+    pub route_limits: Vec<(String, Rate)>,
+    pub max_connections_per_ip: usize,
+    pub compression: bool,
+    pub compress_min_size: u64,
+    /// (path prefix, `Cache-Control` value) pairs.
+    pub cache_control: Vec<(String, String)>,
+    pub file_cache_size: u64,
+    pub file_cache_max_file: u64,
+    /// (URL path, script) pairs.
+    pub cgi: Vec<(String, PathBuf)>,
+    pub cgi_timeout: Duration,
+    /// Document roots whose directories get generated listings.
+    pub listings: Vec<PathBuf>,
+    /// (path prefix, upstream addresses) pairs.
+    pub proxy: Vec<(String, Vec<String>)>,
+    /// Path prefixes that need authentication.
+    pub protect: Vec<String>,
+    pub auth_users: Option<PathBuf>,
+    pub auth_tokens: Option<PathBuf>,
+    pub auth_realm: String,
+    /// (host name, document root) pairs; other hosts get `document_root`.
+    pub vhosts: Vec<(String, PathBuf)>,
+    pub access_log: AccessLogTarget,
+    pub log_format: LogFormat,
+    pub log_level: Level,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let settings = ConnectionSettings::default();
+        Config {
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 7878))],
+            tls_bind: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            workers: 4,
+            document_root: PathBuf::from("public"),
+            idle_timeout: settings.idle_timeout,
+            request_timeout: settings.request_timeout,
+            grace_period: Duration::from_secs(30),
+            max_requests: settings.max_requests,
+            max_body_len: settings.limits.max_body_len,
+            rate_limit: None,
+            route_limits: Vec::new(),
+            max_connections_per_ip: 0,
+            compression: true,
+            compress_min_size: 1024,
+            cache_control: Vec::new(),
+            file_cache_size: 16 * 1024 * 1024,
+            file_cache_max_file: 1024 * 1024,
+            cgi: Vec::new(),
+            cgi_timeout: Duration::from_secs(30),
+            listings: Vec::new(),
+            proxy: Vec::new(),
+            protect: Vec::new(),
+            auth_users: None,
+            auth_tokens: None,
+            auth_realm: "restricted".to_string(),
+            vhosts: Vec::new(),
+            access_log: AccessLogTarget::Stdout,
+            log_format: LogFormat::Combined,
+            log_level: Level::Info,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the config from the command line; `args[0]` is the program
+    /// path. A `--config` file is applied first so flags override it.
+    /// Returns `Ok(None)` when `--help` was asked for.
+    pub fn build(args: &[String]) -> Result<Option<Config>, String> {
+        let mut flags = Vec::new();
+        let mut config_file = None;
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(None);
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("unexpected argument `{arg}`"));
+            };
+            // both `--flag value` and `--flag=value` are accepted
+            let (key, value) = match flag.split_once('=') {
+                Some((key, value)) => (key, value.to_string()),
+                None => match args.next() {
+                    Some(value) => (flag, value.clone()),
+                    None => return Err(format!("`--{flag}` needs a value")),
+                },
+            };
+            if key == "config" {
+                config_file = Some(value);
+            } else {
+                flags.push((key.to_string(), value));
+            }
+        }
+
+        let mut config = Config::default();
+        if let Some(path) = config_file {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format!("can't read config file `{path}`: {e}"))?;
+            config
+                .apply_file(&contents)
+                .map_err(|e| format!("{path}: {e}"))?;
+        }
+        for (key, value) in flags {
+            config
+                .set(&key, &value)
+                .map_err(|e| format!("--{key}: {e}"))?;
+        }
+
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    fn apply_file(&mut self, contents: &str) -> Result<(), String> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = value`", number + 1))?;
+            let key = key.trim();
+            self.set(key, value.trim())
+                .map_err(|e| format!("line {} ({key}): {e}", number + 1))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key.replace('_', "-").as_str() {
+            "bind" => self.bind = parse_addresses(value)?,
+            "tls-bind" => self.tls_bind = parse_addresses(value)?,
+            "tls-cert" => self.tls_cert = Some(PathBuf::from(value)),
+            "tls-key" => self.tls_key = Some(PathBuf::from(value)),
+            "workers" => self.workers = parse_number(value)?,
+            "root" | "document-root" => self.document_root = PathBuf::from(value),
+            "idle-timeout" => self.idle_timeout = parse_seconds(value)?,
+            "request-timeout" => self.request_timeout = parse_seconds(value)?,
+            "grace-period" => self.grace_period = parse_seconds(value)?,
+            "max-requests" => self.max_requests = parse_number(value)?,
+            "max-body" => self.max_body_len = parse_number(value)?,
+            "rate-limit" => self.rate_limit = Some(value.parse()?),
+            "route-limit" => {
+                let (prefix, rate) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("`{value}` is not `PREFIX RATE`"))?;
+                self.route_limits.push((prefix.to_string(), rate.parse()?));
+            }
+            "max-conns-per-ip" => self.max_connections_per_ip = parse_number(value)?,
+            "compression" => self.compression = parse_switch(value)?,
+            "compress-min" => self.compress_min_size = parse_number(value)? as u64,
+            "cache-control" => {
+                let (prefix, directives) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("`{value}` is not `PREFIX VALUE`"))?;
+                self.cache_control
+                    .push((prefix.to_string(), directives.trim().to_string()));
+            }
+            "cgi" => {
+                let (path, script) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("`{value}` is not `PATH FILE`"))?;
+                self.cgi
+                    .push((path.to_string(), PathBuf::from(script.trim())));
+            }
+            "cgi-timeout" => self.cgi_timeout = parse_seconds(value)?,
+            "listings" => self.listings.push(PathBuf::from(value)),
+            "proxy" => {
+                let (prefix, upstreams) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("`{value}` is not `PREFIX HOST:PORT,...`"))?;
+                let upstreams = upstreams.split(',').map(|u| u.trim().to_string()).collect();
+                self.proxy.push((prefix.to_string(), upstreams));
+            }
+            "protect" => self.protect.push(value.to_string()),
+            "auth-users" => self.auth_users = Some(PathBuf::from(value)),
+            "auth-tokens" => self.auth_tokens = Some(PathBuf::from(value)),
+            "auth-realm" => self.auth_realm = value.to_string(),
+            "vhost" => {
+                let (host, root) = value
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("`{value}` is not `HOST DIR`"))?;
+                self.vhosts
+                    .push((host.to_string(), PathBuf::from(root.trim())));
+            }
+            "file-cache" => self.file_cache_size = parse_number(value)? as u64,
+            "file-cache-max" => self.file_cache_max_file = parse_number(value)? as u64,
+            "access-log" => {
+                self.access_log = match value {
+                    "off" => AccessLogTarget::Off,
+                    "stdout" | "-" => AccessLogTarget::Stdout,
+                    path => AccessLogTarget::File(PathBuf::from(path)),
+                }
+            }
+            "log-format" => self.log_format = value.parse()?,
+            "log-level" => self.log_level = value.parse()?,
+            _ => return Err(format!("unknown setting `{key}`")),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.bind.is_empty() && self.tls_bind.is_empty() {
+            return Err("at least one bind address is needed".to_string());
+        }
+        if !self.tls_bind.is_empty() {
+            if !cfg!(feature = "tls") {
+                return Err(
+                    "tls-bind needs a build with the `tls` feature (cargo run --features tls)"
+                        .to_string(),
+                );
+            }
+            if self.tls_cert.is_none() || self.tls_key.is_none() {
+                return Err("tls-bind needs both tls-cert and tls-key".to_string());
+            }
+        }
+        if self.workers == 0 {
+            return Err("workers must be at least 1".to_string());
+        }
+        if self.max_requests == 0 {
+            return Err("max-requests must be at least 1".to_string());
+        }
+        if self.request_timeout.is_zero() || self.idle_timeout.is_zero() {
+            return Err("timeouts must be at least 1 second".to_string());
+        }
+        if !self.document_root.is_dir() {
+            return Err(format!(
+                "document root `{}` is not a directory",
+                self.document_root.display()
+            ));
+        }
+        if !self.protect.is_empty() && self.auth_users.is_none() && self.auth_tokens.is_none() {
+            return Err("protect needs auth-users or auth-tokens".to_string());
+        }
+        for (_, script) in &self.cgi {
+            if !script.is_file() {
+                return Err(format!("CGI script `{}` is not a file", script.display()));
+            }
+        }
+        for root in &self.listings {
+            let is_root = *root == self.document_root || self.vhosts.iter().any(|(_, r)| r == root);
+            if !is_root {
+                return Err(format!(
+                    "listings for `{}`, which is not a document root",
+                    root.display()
+                ));
+            }
+        }
+        for (prefix, upstreams) in &self.proxy {
+            if upstreams.iter().any(String::is_empty) {
+                return Err(format!("proxy for {prefix} lists an empty upstream"));
+            }
+        }
+        for (host, root) in &self.vhosts {
+            if !root.is_dir() {
+                return Err(format!(
+                    "document root `{}` of {host} is not a directory",
+                    root.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn connection_settings(&self) -> ConnectionSettings {
+        ConnectionSettings {
+            limits: Limits {
+                max_body_len: self.max_body_len,
+                ..Limits::default()
+            },
+            idle_timeout: self.idle_timeout,
+            request_timeout: self.request_timeout,
+            max_requests: self.max_requests,
+        }
+    }
+}
+
+/// Parses a comma separated list of addresses; an empty value disables the
+/// listener.
+fn parse_addresses(value: &str) -> Result<Vec<SocketAddr>, String> {
+    if value.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    value
+        .split(',')
+        .map(|addr| {
+            let addr = addr.trim();
+            addr.parse()
+                .map_err(|_| format!("`{addr}` is not an address like 127.0.0.1:7878"))
+        })
+        .collect()
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a whole number"))
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("`{value}` is not `on` or `off`")),
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    parse_number(value).map(|secs| Duration::from_secs(secs as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Vec<String> {
+        std::iter::once("server")
+            .chain(flags.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let config = Config::build(&args(&[
+            "--bind",
+            "127.0.0.1:8080,[::1]:8080",
+            "--workers=8",
+            "--root",
+            ".",
+            "--log-format",
+            "json",
+        ]))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(config.bind.len(), 2);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.document_root, PathBuf::from("."));
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.idle_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reads_config_file_lines() {
+        let mut config = Config::default();
+        config
+            .apply_file(
+                "# comment\n\nworkers = 2\nidle_timeout = 30\naccess-log = off\n\
+                 cache-control = /assets/ public, max-age=86400\n\
+                 vhost = *.example.com  sites/example\n",
+            )
+            .unwrap();
+
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.idle_timeout, Duration::from_secs(30));
+        assert_eq!(config.access_log, AccessLogTarget::Off);
+        assert_eq!(
+            config.cache_control,
+            [("/assets/".to_string(), "public, max-age=86400".to_string())]
+        );
+        assert_eq!(
+            config.vhosts,
+            [("*.example.com".to_string(), PathBuf::from("sites/example"))]
+        );
+    }
+
+    #[test]
+    fn reports_bad_values() {
+        let err = Config::build(&args(&["--workers", "0", "--root", "."])).unwrap_err();
+        assert_eq!(err, "workers must be at least 1");
+
+        let err = Config::build(&args(&["--bind", "localhost"])).unwrap_err();
+        assert!(err.starts_with("--bind: `localhost` is not an address"));
+
+        let err = Config::default().apply_file("workers 2").unwrap_err();
+        assert_eq!(err, "line 1: expected `key = value`");
+
+        for flags in [
+            &["--no-such-flag"][..],
+            &["--workers"],
+            &["--rate-limit", "10/d"],
+            &["--route-limit", "/api"],
+            &["--proxy", "/api"],
+            &["--vhost", "example.com"],
+            &["--cgi", "/cgi-bin"],
+            &["--grace-period", "-1"],
+            &["--log-format", "xml"],
+        ] {
+            assert!(Config::build(&args(flags)).is_err(), "{flags:?}");
+        }
+
+        assert!(Config::build(&args(&["--help"])).unwrap().is_none());
+    }
+}
@@ -0,0 +1,722 @@
+use crate::{
+    request::{percent_decode, Headers, Request},
+    response::StatusCode,
+};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Name/value pairs from a query string or an urlencoded body, in order.
+/// Names may repeat, e.g. for checkboxes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+}
+
+impl Form {
+    /// Parses `application/x-www-form-urlencoded` data: `&`-separated pairs,
+    /// `+` for spaces and `%XX` escapes.
+    pub fn parse(input: &str) -> Result<Form, FormError> {
+        let mut fields = Vec::new();
+        for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| {
+                percent_decode(&s.replace('+', " "))
+                    .ok_or_else(|| FormError::Malformed(format!("bad escape in `{s}`")))
+            };
+            fields.push((decode(name)?, decode(value)?));
+        }
+        Ok(Form { fields })
+    }
+
+    /// The first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The value for `name` converted to `T`, e.g. `form.value::<u32>("age")`.
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        let value = self
+            .get(name)
+            .ok_or_else(|| FormError::Missing(name.to_string()))?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| FormError::Invalid(name.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Part headers longer than this are refused.
+const MAX_PART_HEAD_LEN: usize = 8 * 1024;
+
+/// What is read of a streamed body after the last part, so the connection
+/// can take another request; a longer epilogue closes it instead.
+const MAX_EPILOGUE_LEN: u64 = 1024;
+
+/// Bounds for `multipart/form-data` bodies.
+///
+/// A body the server read in full is also bounded by `Limits::max_body_len`;
+/// for handlers that stream it, see `Handler::streams_body`, these are the
+/// only bounds.
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    pub max_parts: usize,
+    pub max_part_len: usize,
+    /// All parts together.
+    pub max_total_len: usize,
+    /// Parts larger than this go to a temporary file instead of memory.
+    pub memory_threshold: usize,
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_parts: 100,
+            max_part_len: 10 * 1024 * 1024,
+            max_total_len: 50 * 1024 * 1024,
+            memory_threshold: 64 * 1024,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// The parts of a `multipart/form-data` body.
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub parts: Vec<Part>,
+}
+
+impl Multipart {
+    /// Splits `body`, received in full, at the `boundary` from the request's
+    /// `Content-Type`.
+    pub fn parse(
+        body: &[u8],
+        boundary: &str,
+        limits: &MultipartLimits,
+    ) -> Result<Multipart, FormError> {
+        Multipart::read_from(body, boundary, limits)
+    }
+
+    /// Reads the parts from `reader` as they arrive, at the `boundary` from
+    /// the request's `Content-Type`. Parts larger than `memory_threshold` are
+    /// written to their temporary file while they are read.
+    pub fn read_from<R: BufRead>(
+        reader: R,
+        boundary: &str,
+        limits: &MultipartLimits,
+    ) -> Result<Multipart, FormError> {
+        let malformed = |what: &str| FormError::Malformed(format!("multipart body: {what}"));
+        // every delimiter but the first follows a line break, one in front of
+        // the body makes them all alike
+        let delimiter = format!("\r\n--{boundary}").into_bytes();
+        let mut input = Delimited {
+            reader,
+            pending: b"\r\n".to_vec(),
+        };
+        // the preamble before the first delimiter is ignored
+        if !input.read_until(&delimiter, |_| Ok(()))? {
+            return Err(malformed("no boundary"));
+        }
+
+        let mut parts = Vec::new();
+        let mut total = 0;
+        loop {
+            // after a delimiter comes `--` for the last one or a line break
+            match input.peek(2)? {
+                b"--" => return Ok(Multipart { parts }),
+                b"\r\n" => input.pending.drain(..2),
+                _ => return Err(malformed("missing line break after boundary")),
+            };
+
+            let mut head = Vec::new();
+            let complete = input.read_until(b"\r\n\r\n", |bytes| {
+                head.extend_from_slice(bytes);
+                if head.len() > MAX_PART_HEAD_LEN {
+                    return Err(FormError::TooLarge("part headers too large"));
+                }
+                Ok(())
+            })?;
+            if !complete {
+                return Err(malformed("unterminated headers"));
+            }
+            let headers = parse_part_headers(&head).ok_or_else(|| malformed("bad headers"))?;
+            if parts.len() == limits.max_parts {
+                return Err(FormError::TooLarge("too many parts"));
+            }
+            let mut part = Part::new(headers)?;
+
+            let mut data = PartWriter::new(limits);
+            let complete = input.read_until(&delimiter, |bytes| {
+                total += bytes.len();
+                if total > limits.max_total_len {
+                    return Err(FormError::TooLarge("form too large"));
+                }
+                data.write(bytes)
+            })?;
+            if !complete {
+                return Err(malformed("missing closing boundary"));
+            }
+            part.data = data.finish()?;
+            parts.push(part);
+        }
+    }
+
+    /// The first part named `name`.
+    pub fn part(&self, name: &str) -> Option<&Part> {
+        self.parts.iter().find(|part| part.name == name)
+    }
+
+    /// The text of the field `name`, for parts that aren't file uploads.
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.part(name).and_then(|part| part.text())
+    }
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub name: String,
+    /// The name of the uploaded file as the client gave it. Don't use it as
+    /// a path, it could be `../../etc/passwd`.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Headers,
+    pub data: PartData,
+}
+
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+impl Part {
+    /// A part with the name and file name from its headers, and no data yet.
+    fn new(headers: Headers) -> Result<Part, FormError> {
+        let disposition = headers
+            .get("Content-Disposition")
+            .ok_or_else(|| FormError::Malformed("part without Content-Disposition".to_string()))?;
+        let (kind, params) = split_params(disposition);
+        if !kind.eq_ignore_ascii_case("form-data") {
+            return Err(FormError::Malformed(format!(
+                "unexpected disposition `{kind}`"
+            )));
+        }
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+        };
+        let name =
+            param("name").ok_or_else(|| FormError::Malformed("part without a name".to_string()))?;
+
+        Ok(Part {
+            name,
+            filename: param("filename"),
+            content_type: headers.get("Content-Type").map(String::from),
+            headers,
+            data: PartData::Memory(Vec::new()),
+        })
+    }
+
+    /// The contents as text, if they are in memory and valid UTF-8.
+    pub fn text(&self) -> Option<&str> {
+        match &self.data {
+            PartData::Memory(bytes) => std::str::from_utf8(bytes).ok(),
+            PartData::File(_) => None,
+        }
+    }
+
+    /// The contents, read back from the temporary file if needed.
+    pub fn bytes(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File(file) => fs::read(file.path()),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(bytes) => bytes.len() as u64,
+            PartData::File(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Saves the contents to `path`, moving the temporary file there when
+    /// possible instead of copying it.
+    pub fn persist(self, path: &Path) -> io::Result<()> {
+        match self.data {
+            PartData::Memory(bytes) => fs::write(path, bytes),
+            PartData::File(mut file) => {
+                if fs::rename(&file.path, path).is_err() {
+                    // a rename can't cross file systems
+                    fs::copy(&file.path, path)?;
+                    return Ok(());
+                }
+                file.path = PathBuf::new();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A file that is deleted again when dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl TempFile {
+    /// An empty file in `dir`, to write to through the returned handle.
+    fn create(dir: &Path) -> io::Result<(TempFile, File)> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "web_server_upload_{}_{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+        // create_new so we never write through a link someone planted there
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok((TempFile { path, len: 0 }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Collects a part's data in memory, moving it to a temporary file once it
+/// grows past `memory_threshold`.
+struct PartWriter<'a> {
+    limits: &'a MultipartLimits,
+    len: usize,
+    memory: Vec<u8>,
+    file: Option<(TempFile, BufWriter<File>)>,
+}
+
+impl PartWriter<'_> {
+    fn new(limits: &MultipartLimits) -> PartWriter<'_> {
+        PartWriter {
+            limits,
+            len: 0,
+            memory: Vec::new(),
+            file: None,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), FormError> {
+        self.len += bytes.len();
+        if self.len > self.limits.max_part_len {
+            return Err(FormError::TooLarge("part too large"));
+        }
+        if self.file.is_none() && self.len > self.limits.memory_threshold {
+            let (temp, file) = TempFile::create(&self.limits.temp_dir)?;
+            let mut file = BufWriter::new(file);
+            file.write_all(&std::mem::take(&mut self.memory))?;
+            self.file = Some((temp, file));
+        }
+        match &mut self.file {
+            Some((_, file)) => file.write_all(bytes)?,
+            None => self.memory.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<PartData, FormError> {
+        match self.file {
+            Some((mut temp, mut file)) => {
+                file.flush()?;
+                temp.len = self.len as u64;
+                Ok(PartData::File(temp))
+            }
+            None => Ok(PartData::Memory(self.memory)),
+        }
+    }
+}
+
+/// A body read up to one delimiter at a time, holding back only what could
+/// be the start of a delimiter split across reads.
+struct Delimited<R> {
+    reader: R,
+    /// Bytes read but not passed on yet.
+    pending: Vec<u8>,
+}
+
+impl<R: BufRead> Delimited<R> {
+    /// Passes everything before `delimiter` to `sink` and drops the
+    /// delimiter. False if the body ended first.
+    fn read_until(
+        &mut self,
+        delimiter: &[u8],
+        mut sink: impl FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<bool, FormError> {
+        loop {
+            if let Some(at) = find(&self.pending, delimiter) {
+                sink(&self.pending[..at])?;
+                self.pending.drain(..at + delimiter.len());
+                return Ok(true);
+            }
+            let safe = self.pending.len().saturating_sub(delimiter.len() - 1);
+            sink(&self.pending[..safe])?;
+            self.pending.drain(..safe);
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// The next `n` bytes, or fewer if the body ends before.
+    fn peek(&mut self, n: usize) -> Result<&[u8], FormError> {
+        while self.pending.len() < n && self.fill()? {}
+        Ok(&self.pending[..n.min(self.pending.len())])
+    }
+
+    /// Reads more of the body; false at its end.
+    fn fill(&mut self) -> Result<bool, FormError> {
+        let read = self
+            .reader
+            .fill_buf()
+            .map_err(|e| FormError::Malformed(format!("multipart body: {e}")))?;
+        let n = read.len();
+        self.pending.extend_from_slice(read);
+        self.reader.consume(n);
+        Ok(n > 0)
+    }
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    UnsupportedMediaType,
+    Malformed(String),
+    TooLarge(&'static str),
+    /// A required field is not there.
+    Missing(String),
+    /// A field can't be converted to the asked for type.
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl FormError {
+    /// Status code to answer the client with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            FormError::TooLarge(_) => StatusCode::ContentTooLarge,
+            FormError::Io(_) => StatusCode::InternalServerError,
+            _ => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType => f.write_str("unsupported form content type"),
+            FormError::Malformed(what) => write!(f, "malformed form: {what}"),
+            FormError::TooLarge(what) => f.write_str(what),
+            FormError::Missing(name) => write!(f, "missing field `{name}`"),
+            FormError::Invalid(name) => write!(f, "invalid value for field `{name}`"),
+            FormError::Io(e) => write!(f, "can't store upload: {e}"),
+        }
+    }
+}
+
+impl Error for FormError {}
+
+impl From<io::Error> for FormError {
+    fn from(e: io::Error) -> FormError {
+        FormError::Io(e)
+    }
+}
+
+impl Request {
+    /// The query string's fields; empty when there is none or it's malformed.
+    pub fn query_params(&self) -> Form {
+        self.query
+            .as_deref()
+            .and_then(|query| Form::parse(query).ok())
+            .unwrap_or_default()
+    }
+
+    /// The fields of an `application/x-www-form-urlencoded` body.
+    pub fn form(&self) -> Result<Form, FormError> {
+        let (media_type, _) = split_params(self.header("Content-Type").unwrap_or(""));
+        if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(FormError::UnsupportedMediaType);
+        }
+        let body = std::str::from_utf8(&self.body)
+            .map_err(|_| FormError::Malformed("body is not UTF-8".to_string()))?;
+        Form::parse(body)
+    }
+
+    /// The parts of a `multipart/form-data` body. A streamed body, see
+    /// `Handler::streams_body`, is read part by part as it arrives, with
+    /// large parts going straight to their temporary files.
+    pub fn multipart(&mut self, limits: &MultipartLimits) -> Result<Multipart, FormError> {
+        let (media_type, params) = split_params(self.header("Content-Type").unwrap_or(""));
+        if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(FormError::UnsupportedMediaType);
+        }
+        let boundary = params
+            .into_iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))
+            .map(|(_, v)| v)
+            .filter(|b| !b.is_empty() && b.len() <= 70)
+            .ok_or_else(|| FormError::Malformed("missing boundary".to_string()))?;
+        let Some(body) = self.body_reader() else {
+            return Multipart::parse(&self.body, &boundary, limits);
+        };
+        let mut reader = BufReader::new(body);
+        let multipart = Multipart::read_from(&mut reader, &boundary, limits)?;
+        // the epilogue is meant to be ignored, but it has to be read for the
+        // connection to carry on
+        let _ = io::copy(&mut reader.take(MAX_EPILOGUE_LEN), &mut io::sink());
+        Ok(multipart)
+    }
+}
+
+/// Splits `type; key=value; key="quoted value"` into the leading value and
+/// its parameters.
+fn split_params(header: &str) -> (&str, Vec<(String, String)>) {
+    let (value, mut rest) = header.split_once(';').unwrap_or((header, ""));
+    let mut params = Vec::new();
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().to_string();
+        let after = after.trim_start();
+        let value;
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => unescaped.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => unescaped.push(c),
+                }
+            }
+            value = unescaped;
+            rest = quoted[end..].split_once(';').map_or("", |(_, r)| r);
+        } else {
+            let (v, r) = after.split_once(';').unwrap_or((after, ""));
+            value = v.trim().to_string();
+            rest = r;
+        }
+        params.push((key, value));
+    }
+    (value.trim(), params)
+}
+
+fn parse_part_headers(head: &[u8]) -> Option<Headers> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut headers = Headers::new();
+    for line in head.split("\r\n") {
+        let (name, value) = line.split_once(':')?;
+        headers.append(name.trim(), value.trim());
+    }
+    Some(headers)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn parses_urlencoded_fields() {
+        let form = Form::parse("name=Ferris+the+Crab&age=7&tag=a&tag=b%26c&empty").unwrap();
+
+        assert_eq!(form.get("name"), Some("Ferris the Crab"));
+        assert_eq!(form.value::<u32>("age").unwrap(), 7);
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["a", "b&c"]);
+        assert_eq!(form.get("empty"), Some(""));
+        assert!(matches!(
+            form.value::<u32>("name"),
+            Err(FormError::Invalid(_))
+        ));
+        assert!(matches!(
+            form.value::<u32>("nope"),
+            Err(FormError::Missing(_))
+        ));
+        assert!(Form::parse("bad=%zz").is_err());
+
+        let req = request("GET /search?q=rust%20book HTTP/1.1\r\n\r\n");
+        assert_eq!(req.query_params().get("q"), Some("rust book"));
+    }
+
+    #[test]
+    fn parses_multipart_parts() {
+        let body = "--XyZ\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                    Hello\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\n\
+                    0123456789\r\n\
+                    --XyZ--\r\n";
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"XyZ\"\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut req = request(&raw);
+
+        let limits = MultipartLimits {
+            memory_threshold: 5,
+            ..MultipartLimits::default()
+        };
+        let form = req.multipart(&limits).unwrap();
+        assert_eq!(form.text("title"), Some("Hello"));
+
+        let file = form.part("file").unwrap();
+        assert_eq!(file.filename.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        let PartData::File(temp) = &file.data else {
+            panic!("large part should be on disk");
+        };
+        let path = temp.path().to_path_buf();
+        assert_eq!(file.bytes().unwrap(), b"0123456789");
+        drop(form);
+        assert!(!path.exists());
+
+        let tight = MultipartLimits {
+            max_part_len: 8,
+            ..MultipartLimits::default()
+        };
+        assert!(matches!(req.multipart(&tight), Err(FormError::TooLarge(_))));
+        assert!(matches!(req.form(), Err(FormError::UnsupportedMediaType)));
+    }
+
+    #[test]
+    fn rejects_bad_multipart_bodies() {
+        let part = |name: &str, data: &str| {
+            format!("--b\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{data}\r\n")
+        };
+        let parse =
+            |body: &str, limits: &MultipartLimits| Multipart::parse(body.as_bytes(), "b", limits);
+        let limits = MultipartLimits::default();
+        let two = format!("{}{}--b--\r\n", part("a", "12345"), part("b", "67890"));
+        assert_eq!(parse(&two, &limits).unwrap().parts.len(), 2);
+
+        let few_parts = MultipartLimits {
+            max_parts: 1,
+            ..MultipartLimits::default()
+        };
+        assert!(matches!(
+            parse(&two, &few_parts),
+            Err(FormError::TooLarge(_))
+        ));
+        let small_total = MultipartLimits {
+            max_total_len: 9,
+            ..MultipartLimits::default()
+        };
+        assert!(matches!(
+            parse(&two, &small_total),
+            Err(FormError::TooLarge(_))
+        ));
+
+        for body in [
+            "no delimiter at all".to_string(),
+            part("a", "unterminated"),
+            "--b\r\nContent-Disposition: form-data\r\n\r\nx\r\n--b--".to_string(),
+            "--b\r\nContent-Disposition: attachment; name=a\r\n\r\nx\r\n--b--".to_string(),
+            "--b\r\nContent-Disposition: form-data; name=a\r\nx\r\n--b--".to_string(),
+        ] {
+            assert!(
+                matches!(parse(&body, &limits), Err(FormError::Malformed(_))),
+                "{body:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_delimiters_split_across_reads() {
+        let body = "preamble\r\n--b\r\n\
+                    Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+                    one\r\n--\r\n\
+                    --b\r\n\
+                    Content-Disposition: form-data; name=\"f\"; filename=\"f.bin\"\r\n\r\n\
+                    0123456789\r\n\
+                    --b--\r\n";
+        let limits = MultipartLimits {
+            memory_threshold: 8,
+            ..MultipartLimits::default()
+        };
+        // one byte at a time, so every delimiter arrives in pieces
+        let reader = io::BufReader::with_capacity(1, body.as_bytes());
+        let form = Multipart::read_from(reader, "b", &limits).unwrap();
+        assert_eq!(form.text("a"), Some("one\r\n--"));
+        let file = form.part("f").unwrap();
+        assert!(matches!(&file.data, PartData::File(temp) if temp.len == 10));
+        assert_eq!(file.bytes().unwrap(), b"0123456789");
+
+        let tight = MultipartLimits {
+            max_part_len: 9,
+            ..limits
+        };
+        let reader = io::BufReader::with_capacity(1, body.as_bytes());
+        assert!(matches!(
+            Multipart::read_from(reader, "b", &tight),
+            Err(FormError::TooLarge(_))
+        ));
+    }
+}
@@ -1,4 +1,34 @@
+pub mod auth;
+pub mod base64;
+pub mod cache;
+pub mod cgi;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod date;
+pub mod form;
+pub mod json;
+pub mod log;
+pub mod middleware;
+pub mod proxy;
+pub mod range;
+pub mod ratelimit;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod sha1;
+pub mod shutdown;
+pub mod sse;
+pub mod static_files;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod vhost;
+pub mod websocket;
+
+use log::Level;
 use std::{
+    panic::{self, AssertUnwindSafe},
     sync::{mpsc, Arc, Mutex},
     thread,
 };
@@ -44,6 +74,16 @@ impl ThreadPool {
 
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Lets the workers finish on their own instead of waiting for them, for
+    /// when some are stuck in a job that won't end soon.
+    pub fn detach(mut self) {
+        drop(self.sender.take());
+        for worker in &mut self.workers {
+            // dropping a JoinHandle detaches the thread
+            worker.thread.take();
+        }
+    }
 }
 
 impl Drop for ThreadPool {
@@ -51,7 +91,7 @@ impl Drop for ThreadPool {
         drop(self.sender.take());
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            log!(Level::Debug, "shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
                 thread.join().unwrap();
@@ -72,12 +112,16 @@ impl Worker {
 
             match message {
                 Ok(job) => {
-                    println!("Worker {id} got a job; executing.");
+                    log!(Level::Debug, "worker {id} got a job; executing");
 
-                    job();
+                    // a panicking job mustn't take the worker down with it, the
+                    // pool would be a thread short and `execute` fail once all are gone
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        log!(Level::Error, "worker {id} recovered from a panicking job");
+                    }
                 }
                 Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
+                    log!(Level::Debug, "worker {id} disconnected; shutting down");
                     break;
                 }
             }
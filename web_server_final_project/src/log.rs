@@ -0,0 +1,247 @@
+use crate::{
+    date::{format_clf_date, format_http_date},
+    json::quote,
+};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!(
+                "unknown log level `{s}`, expected error, warn, info or debug"
+            )),
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Sets the most verbose level that still gets printed.
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Used by the `log!` macro; prints a diagnostic line to stderr.
+pub fn write(level: Level, args: fmt::Arguments) {
+    eprintln!(
+        "[{} {}] {args}",
+        format_http_date(SystemTime::now()),
+        level.as_str()
+    );
+}
+
+/// Prints a diagnostic message to stderr if `level` is enabled:
+/// `log!(Level::Info, "listening on {addr}")`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format used by Apache and nginx.
+    Common,
+    /// Common Log Format plus `Referer` and `User-Agent`.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "unknown log format `{s}`, expected common, combined or json"
+            )),
+        }
+    }
+}
+
+/// What is recorded about one request.
+pub struct AccessEntry<'a> {
+    pub remote_addr: Option<SocketAddr>,
+    pub time: SystemTime,
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration: Duration,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// Writes one line per request to stdout or a file.
+pub struct AccessLog {
+    format: LogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn stdout(format: LogFormat) -> AccessLog {
+        AccessLog {
+            format,
+            out: Mutex::new(Box::new(io::stdout())),
+        }
+    }
+
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn file(path: &Path, format: LogFormat) -> io::Result<AccessLog> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog {
+            format,
+            out: Mutex::new(Box::new(file)),
+        })
+    }
+
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = self.format_entry(entry);
+        let mut out = self.out.lock().unwrap();
+        // a full disk shouldn't take the server down with it
+        if let Err(e) = writeln!(out, "{line}").and_then(|_| out.flush()) {
+            crate::log!(Level::Error, "failed to write access log: {e}");
+        }
+    }
+
+    fn format_entry(&self, entry: &AccessEntry) -> String {
+        let remote = entry
+            .remote_addr
+            .map(|a| a.ip().to_string())
+            .unwrap_or_else(|| "-".to_string());
+
+        match self.format {
+            LogFormat::Common | LogFormat::Combined => {
+                let mut line = format!(
+                    "{remote} - - [{}] \"{} {} {}\" {} {}",
+                    format_clf_date(entry.time),
+                    entry.method,
+                    clf_escape(entry.target),
+                    entry.version,
+                    entry.status,
+                    entry.bytes
+                );
+                if self.format == LogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        entry.referer.map_or("-".to_string(), clf_escape),
+                        entry.user_agent.map_or("-".to_string(), clf_escape)
+                    ));
+                }
+                line
+            }
+            LogFormat::Json => format!(
+                "{{\"time\":\"{}\",\"remote_addr\":\"{remote}\",\"method\":\"{}\",\"path\":{},\
+                 \"version\":\"{}\",\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\
+                 \"referer\":{},\"user_agent\":{}}}",
+                format_http_date(entry.time),
+                entry.method,
+                quote(entry.target),
+                entry.version,
+                entry.status,
+                entry.bytes,
+                entry.duration.as_secs_f64() * 1000.0,
+                entry.referer.map_or("null".to_string(), quote),
+                entry.user_agent.map_or("null".to_string(), quote),
+            ),
+        }
+    }
+}
+
+/// Escapes quotes and backslashes the way Apache does inside quoted fields.
+fn clf_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> AccessEntry<'static> {
+        AccessEntry {
+            remote_addr: Some("127.0.0.1:50000".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: "GET",
+            target: "/apache_pb.gif",
+            version: "HTTP/1.0",
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_millis(3),
+            referer: None,
+            user_agent: Some("curl/8.0 \"quoted\""),
+        }
+    }
+
+    #[test]
+    fn formats_common_and_combined() {
+        let common = AccessLog::stdout(LogFormat::Common).format_entry(&entry());
+        assert_eq!(
+            common,
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326"
+        );
+
+        let combined = AccessLog::stdout(LogFormat::Combined).format_entry(&entry());
+        assert!(combined.ends_with(" 200 2326 \"-\" \"curl/8.0 \\\"quoted\\\"\""));
+    }
+
+    #[test]
+    fn formats_json() {
+        let json = AccessLog::stdout(LogFormat::Json).format_entry(&entry());
+
+        assert!(json.starts_with('{') && json.ends_with('}'));
+        assert!(json.contains("\"status\":200"));
+        assert!(json.contains("\"duration_ms\":3.000"));
+        assert!(json.contains("\"user_agent\":\"curl/8.0 \\\"quoted\\\"\""));
+        assert!(json.contains("\"referer\":null"));
+    }
+}
@@ -1,46 +1,239 @@
 // discusion and explanations in https://rust-book.cs.brown.edu/ch20-00-final-project-a-web-server.html
 
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    env, io,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process,
+    sync::Arc,
     thread,
     time::Duration,
 };
-use web_server_final_project::ThreadPool;
+#[cfg(feature = "tls")]
+use web_server_final_project::tls::TlsAcceptor;
+use web_server_final_project::{
+    auth::{Auth, Htpasswd, Tokens},
+    cache::{CacheControl, FileCache},
+    cgi::Cgi,
+    compression::Compression,
+    config::{AccessLogTarget, Config, USAGE},
+    log,
+    log::{AccessLog, Level},
+    middleware::{Chain, Recover, RequestId, Timing},
+    proxy::Proxy,
+    ratelimit::RateLimit,
+    request::{Method, Request},
+    response::Response,
+    router::{Handler, Router},
+    server::Server,
+    shutdown::Shutdown,
+    static_files::StaticFiles,
+    vhost::VirtualHosts,
+};
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    let args: Vec<String> = env::args().collect();
 
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+    let config = match Config::build(&args) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("Problem with the configuration: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    log::set_level(config.log_level);
 
-        pool.execute(|| {
-            handle_connection(stream);
+    // RequestId is outermost so even the 500 from a recovered panic carries an id
+    let mut handler = Chain::new(virtual_hosts(&config))
+        .with(RequestId::new())
+        .with(Recover)
+        .with(Timing);
+    if config.rate_limit.is_some() || !config.route_limits.is_empty() {
+        handler = handler.with(rate_limit(&config));
+    }
+    if !config.protect.is_empty() {
+        handler = handler.with(auth(&config));
+    }
+    handler = handler.with(cache_control(&config));
+    if config.compression {
+        handler = handler.with(Compression {
+            min_size: config.compress_min_size,
+            ..Compression::default()
         });
     }
+    let mut server = Server::new(handler, config.workers);
+    for addr in &config.bind {
+        server = server.listener(bind(addr));
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let acceptor = TlsAcceptor::from_pem_files(cert, key).unwrap_or_else(|err| {
+            eprintln!("Can't set up HTTPS: {err}");
+            process::exit(1);
+        });
+        let acceptor = Arc::new(acceptor);
+        for addr in &config.tls_bind {
+            server = server.tls_listener(bind(addr), Arc::clone(&acceptor));
+        }
+    }
+
+    let access_log = match &config.access_log {
+        AccessLogTarget::Off => None,
+        AccessLogTarget::Stdout => Some(AccessLog::stdout(config.log_format)),
+        AccessLogTarget::File(path) => Some(
+            AccessLog::file(path, config.log_format).unwrap_or_else(|err| {
+                eprintln!("Can't open access log {}: {err}", path.display());
+                process::exit(1);
+            }),
+        ),
+    };
+    if let Some(access_log) = access_log {
+        server = server.access_log(access_log);
+    }
+
+    let result = server
+        .settings(config.connection_settings())
+        .max_connections_per_ip(config.max_connections_per_ip)
+        .grace_period(config.grace_period)
+        .shutdown(Shutdown::from_signals())
+        .run();
+
+    if let Err(err) = result {
+        eprintln!("Server error: {err}");
+        process::exit(1);
+    }
+    log!(Level::Info, "shutting down")
+}
 
-    println!("Shutting down.")
+fn bind(addr: &SocketAddr) -> TcpListener {
+    TcpListener::bind(addr).unwrap_or_else(|err| {
+        eprintln!("Can't listen on {addr}: {err}");
+        process::exit(1);
+    })
+}
+
+/// The main site plus one plain static site per `--vhost`.
+fn virtual_hosts(config: &Config) -> VirtualHosts {
+    config
+        .vhosts
+        .iter()
+        .fold(VirtualHosts::new(router(config)), |hosts, (host, root)| {
+            let files = static_files(root, config);
+            hosts.host(
+                host,
+                Router::new().get("/*", move |req: &mut Request| files.handle(req)),
+            )
+        })
+}
+
+fn static_files(root: &Path, config: &Config) -> StaticFiles {
+    let files = StaticFiles::new(root).with_listings(config.listings.iter().any(|r| r == root));
+    if config.file_cache_size == 0 {
+        return files;
+    }
+    files.with_cache(FileCache::new(
+        config.file_cache_size,
+        config.file_cache_max_file,
+    ))
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
+fn router(config: &Config) -> Router {
+    let files = Arc::new(static_files(&config.document_root, config));
+    let hello = config.document_root.join("hello.html");
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
+    let (home_files, home) = (Arc::clone(&files), hello.clone());
+    let (sleepy_files, sleepy_hello) = (Arc::clone(&files), hello);
+    let mut router = Router::new()
+        .get("/", move |req: &mut Request| {
+            home_files.serve_file(req, &home)
+        })
+        .get("/sleep", move |req: &mut Request| {
             thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello.html")
-        }
-        _ => ("HTTP/1.1 400 NOT FOUND", "404.html"),
-    };
+            sleepy_files.serve_file(req, &sleepy_hello)
+        });
+    // before the static files, which would take every GET
+    for (prefix, upstreams) in &config.proxy {
+        router = mount(router, prefix, Proxy::new(upstreams.clone()));
+    }
+    for (prefix, script) in &config.cgi {
+        router = mount(
+            router,
+            prefix,
+            Cgi::new(script).with_timeout(config.cgi_timeout),
+        );
+    }
+    router.get("/*", move |req: &mut Request| files.handle(req))
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+/// One handler behind several routes.
+struct Shared<H>(Arc<H>);
+
+impl<H: Handler> Handler for Shared<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        self.0.handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.0.streams_body(request)
+    }
+}
 
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+/// Routes requests of every method for `prefix` and the paths below it to
+/// `handler`.
+fn mount<H: Handler + 'static>(mut router: Router, prefix: &str, handler: H) -> Router {
+    let handler = Arc::new(handler);
+    let pattern = format!("{}/*", prefix.trim_end_matches('/'));
+    for method in [
+        Method::Get,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Options,
+        Method::Patch,
+    ] {
+        router = router.route(method, &pattern, Shared(Arc::clone(&handler)));
+    }
+    router
+}
+
+fn auth(config: &Config) -> Auth {
+    let load_failed = |path: &Path, err: io::Error| -> ! {
+        eprintln!("Can't load credentials from {}: {err}", path.display());
+        process::exit(1);
+    };
+    let mut auth = Auth::new(&config.auth_realm);
+    if let Some(path) = &config.auth_users {
+        auth = auth.with_basic(Htpasswd::load(path).unwrap_or_else(|err| load_failed(path, err)));
+    }
+    if let Some(path) = &config.auth_tokens {
+        auth = auth.with_tokens(Tokens::load(path).unwrap_or_else(|err| load_failed(path, err)));
+    }
+    config
+        .protect
+        .iter()
+        .fold(auth, |auth, prefix| auth.protect(prefix))
+}
+
+fn rate_limit(config: &Config) -> RateLimit {
+    let limit = match config.rate_limit {
+        Some(rate) => RateLimit::new(rate),
+        None => RateLimit::unlimited(),
+    };
+    config
+        .route_limits
+        .iter()
+        .fold(limit, |limit, (prefix, rate)| limit.route(prefix, *rate))
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
+fn cache_control(config: &Config) -> CacheControl {
+    config
+        .cache_control
+        .iter()
+        .fold(CacheControl::new(), |rules, (prefix, value)| {
+            rules.rule(prefix, value)
+        })
 }
@@ -0,0 +1,248 @@
+use crate::{
+    log,
+    log::Level,
+    request::Request,
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Code that runs around a handler. It can change the request before calling
+/// `next`, change the response `next` returns, or answer without calling it.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response;
+}
+
+/// A handler wrapped in middlewares. The first one added is the outermost:
+/// it sees the request first and the response last.
+pub struct Chain {
+    middlewares: Vec<Arc<dyn Middleware>>,
+    handler: Arc<dyn Handler>,
+}
+
+impl Chain {
+    pub fn new<H: Handler + 'static>(handler: H) -> Chain {
+        Chain {
+            middlewares: Vec::new(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, request: &mut Request) -> Response {
+        Next {
+            middlewares: &self.middlewares,
+            handler: &*self.handler,
+        }
+        .handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.handler.streams_body(request)
+    }
+}
+
+/// The rest of the chain, as seen from one middleware.
+struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Handler for Next<'_> {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => first.handle(
+                request,
+                &Next {
+                    middlewares: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(request),
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.handler.streams_body(request)
+    }
+}
+
+/// Tags every request with an `X-Request-Id`, reusing the one a proxy in
+/// front of us sent, and echoes it in the response.
+pub struct RequestId {
+    prefix: String,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        // the start time keeps ids from different runs of the server apart
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        RequestId {
+            prefix: format!("{started:x}"),
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let incoming = request.header("X-Request-Id").filter(|id| {
+            // only pass on ids that are safe to copy into headers and logs
+            !id.is_empty()
+                && id.len() <= 128
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        });
+        let id = match incoming {
+            Some(id) => id.to_string(),
+            None => {
+                let n = self.counter.fetch_add(1, Ordering::Relaxed);
+                format!("{}-{n}", self.prefix)
+            }
+        };
+        request.headers.insert("X-Request-Id", &id);
+
+        let mut response = next.handle(request);
+        response.headers.insert("X-Request-Id", &id);
+        response
+    }
+}
+
+/// Adds a `Server-Timing` header with the time spent in the handlers inside
+/// it, in milliseconds.
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let started = Instant::now();
+        let mut response = next.handle(request);
+        let millis = started.elapsed().as_secs_f64() * 1000.0;
+        response
+            .headers
+            .append("Server-Timing", &format!("app;dur={millis:.3}"));
+        response
+    }
+}
+
+/// Turns a panicking handler into a 500 response, so one bad request neither
+/// kills the worker thread nor leaves the client without an answer.
+///
+/// Code that takes over the connection after the response head, like a
+/// WebSocket or event stream, runs after this has returned. The server
+/// catches its panics itself and closes the connection, as the status has
+/// already been sent.
+pub struct Recover;
+
+impl Middleware for Recover {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let method = request.method;
+        let path = request.path.clone();
+
+        // the request is thrown away after a panic, so a broken invariant in it can't be observed
+        match panic::catch_unwind(AssertUnwindSafe(|| next.handle(request))) {
+            Ok(response) => response,
+            Err(payload) => {
+                log!(
+                    Level::Error,
+                    "handler for {method} {path} panicked: {}",
+                    panic_message(&*payload)
+                );
+                Response::error(StatusCode::InternalServerError)
+            }
+        }
+    }
+}
+
+/// The message `panic!` was given, if it was a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap()
+    }
+
+    /// Appends its name to `X-Trace` on the way in and out.
+    struct Trace(&'static str);
+
+    impl Middleware for Trace {
+        fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+            request.headers.append("X-Trace", self.0);
+            let mut response = next.handle(request);
+            response.headers.append("X-Trace", self.0);
+            response
+        }
+    }
+
+    #[test]
+    fn runs_middlewares_in_order() {
+        let chain = Chain::new(|req: &mut Request| {
+            let seen: Vec<&str> = req.headers.get_all("X-Trace").collect();
+            Response::text(seen.join(","))
+        })
+        .with(Trace("outer"))
+        .with(Trace("inner"));
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.body.as_bytes(), Some(&b"outer,inner"[..]));
+        let order: Vec<&str> = response.headers.get_all("X-Trace").collect();
+        assert_eq!(order, ["inner", "outer"]);
+    }
+
+    #[test]
+    fn request_id_is_generated_or_kept() {
+        let chain = Chain::new(|_: &mut Request| Response::text("")).with(RequestId::new());
+
+        let generated = chain.handle(&mut request("GET / HTTP/1.1\r\n\r\n"));
+        assert!(generated.headers.get("X-Request-Id").is_some());
+
+        let kept = chain.handle(&mut request(
+            "GET / HTTP/1.1\r\nX-Request-Id: abc-1\r\n\r\n",
+        ));
+        assert_eq!(kept.headers.get("X-Request-Id"), Some("abc-1"));
+
+        let replaced = chain.handle(&mut request("GET / HTTP/1.1\r\nX-Request-Id: a b\r\n\r\n"));
+        assert_ne!(replaced.headers.get("X-Request-Id"), Some("a b"));
+    }
+
+    #[test]
+    fn recovers_from_panics() {
+        let chain = Chain::new(|_: &mut Request| -> Response { panic!("boom") }).with(Recover);
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(response.status, StatusCode::InternalServerError);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{
+    client::read_head,
+    log,
+    log::Level,
+    request::{invalid_data, is_timeout, BodyReader, ChunkedReader, Headers, Method, Request},
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Why a request could not be forwarded.
+enum Failure {
+    /// Reading the request body from the client.
+    Client(io::Error),
+    /// Talking to the upstream.
+    Upstream(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        Failure::Upstream(e)
+    }
+}
+
+/// Headers that describe one connection rather than the message, so they are
+/// not passed on, see RFC 9110 section 7.6.1.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Forwards requests to one or more upstream servers, taking turns.
+///
+/// An upstream that can't be reached `max_fails` times in a row is left out
+/// for `fail_timeout`; when every upstream is out they are all tried anyway.
+/// Only failed connection attempts move on to the next upstream, since once
+/// a request has been sent it may have had effects.
+///
+/// Bodies are streamed both ways, as they arrive: a request body is read from
+/// the client while it is sent on, see `Handler::streams_body`, so it is not
+/// bounded by `Limits::max_body_len`; that is left to the upstream. A chunked
+/// request body is sent on chunked. Each request uses a new upstream
+/// connection.
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    next: AtomicUsize,
+    timeout: Duration,
+    max_fails: u32,
+    fail_timeout: Duration,
+}
+
+struct Upstream {
+    /// `host:port`
+    addr: String,
+    fails: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn is_down(&self) -> bool {
+        let down_until = self.down_until.lock().unwrap_or_else(|e| e.into_inner());
+        down_until.is_some_and(|until| Instant::now() < until)
+    }
+}
+
+impl Proxy {
+    /// A proxy for upstreams given as `host:port`.
+    pub fn new<S: Into<String>>(upstreams: impl IntoIterator<Item = S>) -> Proxy {
+        let upstreams: Vec<Upstream> = upstreams
+            .into_iter()
+            .map(|addr| Upstream {
+                addr: addr.into(),
+                fails: AtomicU32::new(0),
+                down_until: Mutex::new(None),
+            })
+            .collect();
+        assert!(!upstreams.is_empty(), "a proxy needs at least one upstream");
+        Proxy {
+            upstreams,
+            next: AtomicUsize::new(0),
+            timeout: Duration::from_secs(30),
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// How long connecting, and then each read or write, may take. 30
+    /// seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Failures in a row after which an upstream is left out for
+    /// `fail_timeout`. 1 and 10 seconds by default.
+    pub fn with_health_check(mut self, max_fails: u32, fail_timeout: Duration) -> Proxy {
+        self.max_fails = max_fails.max(1);
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// The upstreams in the order to try them: healthy ones, round-robin.
+    fn candidates(&self) -> Vec<&Upstream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let in_turn =
+            (0..self.upstreams.len()).map(|i| &self.upstreams[(start + i) % self.upstreams.len()]);
+        let healthy: Vec<&Upstream> = in_turn.clone().filter(|u| !u.is_down()).collect();
+        if healthy.is_empty() {
+            in_turn.collect()
+        } else {
+            healthy
+        }
+    }
+
+    fn failed(&self, upstream: &Upstream, err: &io::Error) {
+        let fails = upstream.fails.fetch_add(1, Ordering::Relaxed) + 1;
+        log!(Level::Warn, "upstream {} failed: {err}", upstream.addr);
+        if fails >= self.max_fails {
+            let mut down_until = upstream
+                .down_until
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            *down_until = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    fn succeeded(&self, upstream: &Upstream) {
+        upstream.fails.store(0, Ordering::Relaxed);
+        *upstream
+            .down_until
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn connect(&self, addr: &str) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn forward(&self, upstream: &Upstream, stream: TcpStream, request: &mut Request) -> Response {
+        let exchange = |request: &mut Request| -> Result<Response, Failure> {
+            write_request(&stream, request, &upstream.addr)?;
+            Ok(read_response(
+                BufReader::new(stream.try_clone()?),
+                request.method,
+            )?)
+        };
+        match exchange(request) {
+            Ok(response) => {
+                self.succeeded(upstream);
+                response
+            }
+            // the client's fault, the upstream is fine
+            Err(Failure::Client(e)) => {
+                log!(Level::Debug, "request body from the client failed: {e}");
+                let status = if is_timeout(&e) {
+                    StatusCode::RequestTimeout
+                } else {
+                    StatusCode::BadRequest
+                };
+                Response::error(status)
+            }
+            Err(Failure::Upstream(e)) => {
+                self.failed(upstream, &e);
+                let status = if is_timeout(&e) {
+                    StatusCode::GatewayTimeout
+                } else {
+                    StatusCode::BadGateway
+                };
+                Response::error(status)
+            }
+        }
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &mut Request) -> Response {
+        for upstream in self.candidates() {
+            match self.connect(&upstream.addr) {
+                Ok(stream) => return self.forward(upstream, stream, request),
+                Err(e) => self.failed(upstream, &e),
+            }
+        }
+        Response::error(StatusCode::BadGateway)
+    }
+
+    fn streams_body(&self, _request: &Request) -> bool {
+        true
+    }
+}
+
+/// Removes the hop-by-hop headers, including those the `Connection` header
+/// names.
+fn strip_hop_by_hop(headers: &Headers) -> Headers {
+    let named: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    let mut kept = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop_by_hop = HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+            || named.contains(&name.to_ascii_lowercase());
+        if !hop_by_hop {
+            kept.append(name, value);
+        }
+    }
+    kept
+}
+
+/// Sends the request head and then the body, passing it on as it arrives
+/// when the server streams it.
+fn write_request(stream: &TcpStream, request: &mut Request, upstream: &str) -> Result<(), Failure> {
+    let mut headers = strip_hop_by_hop(&request.headers);
+    headers.remove("Content-Length");
+
+    // each proxy on the way adds the address it got the request from
+    if let Some(client) = request.remote_addr {
+        let forwarded_for = match request.header("X-Forwarded-For") {
+            Some(earlier) => format!("{earlier}, {}", client.ip()),
+            None => client.ip().to_string(),
+        };
+        headers.insert("X-Forwarded-For", &forwarded_for);
+    }
+    match request.header("Host") {
+        Some(host) => headers.insert("X-Forwarded-Host", host),
+        None => headers.insert("Host", upstream),
+    }
+    let length = match request.body_reader() {
+        Some(body) => body.content_length(),
+        None => Some(request.body.len() as u64),
+    };
+    match length {
+        Some(length)
+            if length > 0
+                || matches!(request.method, Method::Post | Method::Put | Method::Patch) =>
+        {
+            headers.insert("Content-Length", &length.to_string());
+        }
+        Some(_) => {}
+        // sent on without waiting for the end, which only the chunks tell
+        None => headers.insert("Transfer-Encoding", "chunked"),
+    }
+    headers.insert("Connection", "close");
+
+    let mut w = BufWriter::new(stream);
+    write!(w, "{} {} HTTP/1.1\r\n", request.method, request.target())?;
+    for (name, value) in headers.iter() {
+        write!(w, "{name}: {value}\r\n")?;
+    }
+    w.write_all(b"\r\n")?;
+    match request.body_reader() {
+        Some(body) => copy_body(body, &mut w, length.is_none())?,
+        None => w.write_all(&request.body)?,
+    }
+    Ok(w.flush()?)
+}
+
+/// Copies the client's body to the upstream, each piece as one chunk if
+/// `chunked`.
+fn copy_body(body: &mut BodyReader, w: &mut impl Write, chunked: bool) -> Result<(), Failure> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let n = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Failure::Client(e)),
+        };
+        if chunked {
+            write!(w, "{n:x}\r\n")?;
+            w.write_all(&buf[..n])?;
+            w.write_all(b"\r\n")?;
+        } else {
+            w.write_all(&buf[..n])?;
+        }
+    }
+    if chunked {
+        w.write_all(b"0\r\n\r\n")?;
+    }
+    Ok(())
+}
+
+fn read_response(mut reader: BufReader<TcpStream>, method: Method) -> io::Result<Response> {
+    // interim responses like `100 Continue` are not passed on
+    let (status, headers) = read_head(&mut reader)?;
+
+    let mut response = Response::new(status);
+    response.headers = strip_hop_by_hop(&headers);
+    response.headers.remove("Content-Length");
+    if !status.allows_body() {
+        return Ok(response);
+    }
+
+    let length = match headers.get("Content-Length") {
+        Some(length) => Some(length.trim().parse().map_err(invalid_data)?),
+        None => None,
+    };
+    let response = if method == Method::Head {
+        // the length describes the body a GET would get, there is none to read
+        response.with_length_only(length)
+    } else if headers.has_token("Transfer-Encoding", "chunked") {
+        response.with_reader(ChunkedReader::new(reader), None)
+    } else {
+        match length {
+            Some(length) => response.with_reader(reader.take(length), Some(length)),
+            // the upstream marks the end by closing the connection
+            None => response.with_reader(reader, None),
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_hop_by_hop_headers() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "keep-alive, X-Secret");
+        headers.append("Keep-Alive", "timeout=5");
+        headers.append("X-Secret", "1");
+        headers.append("Accept", "*/*");
+
+        let kept = strip_hop_by_hop(&headers);
+        let names: Vec<&str> = kept.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["Accept"]);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{
+    date::parse_http_date,
+    request::Request,
+    response::{Response, StatusCode},
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// More ranges than this in one request are not worth the work; the whole
+/// representation is sent instead.
+const MAX_RANGES: usize = 32;
+
+/// Parses a `Range` header against a representation of `len` bytes.
+///
+/// Returns `None` when the header should be ignored (another unit, bad
+/// syntax, too many ranges), otherwise the satisfiable ranges, sorted and
+/// with overlapping ones merged. An empty list means a 416 is due.
+pub fn parse(header: &str, len: u64) -> Option<Vec<Range<u64>>> {
+    let (unit, specs) = header.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for (count, spec) in specs.split(',').enumerate() {
+        if count == MAX_RANGES {
+            return None;
+        }
+        let (first, last) = spec.trim().split_once('-')?;
+        let range = match (first, last) {
+            // the last N bytes
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                len.saturating_sub(suffix)..len
+            }
+            (first, "") => first.parse().ok()?..len,
+            (first, last) => {
+                let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+                if last < first {
+                    return None;
+                }
+                first..last.saturating_add(1).min(len)
+            }
+        };
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Some(merged)
+}
+
+/// Whether an `If-Range` precondition, if any, still holds for the
+/// representation with `etag` and `modified`. When it doesn't, the client's
+/// partial copy is stale and the whole representation has to be sent.
+pub fn if_range_holds(request: &Request, etag: &str, modified: SystemTime) -> bool {
+    let Some(condition) = request.header("If-Range") else {
+        return true;
+    };
+    let condition = condition.trim();
+
+    // weak tags never match here, ranges need byte-for-byte identical representations
+    if condition.starts_with('"') || condition.starts_with("W/") {
+        return condition == etag;
+    }
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+    match parse_http_date(condition) {
+        Some(date) => seconds(date) == seconds(modified),
+        None => false,
+    }
+}
+
+/// The 416 answer, telling the client how long the representation is.
+pub fn not_satisfiable(len: u64) -> Response {
+    Response::error(StatusCode::RangeNotSatisfiable)
+        .with_header("Content-Range", &format!("bytes */{len}"))
+}
+
+/// A 206 response streaming `ranges` of `file`, which is `len` bytes long. A
+/// single range is sent as is, several as `multipart/byteranges`.
+pub fn partial_file(file: File, ranges: &[Range<u64>], len: u64, content_type: &str) -> Response {
+    let response = Response::new(StatusCode::PartialContent);
+
+    if let [range] = ranges {
+        let reader = FileRegions::new(file, vec![Part::Region(range.clone())]);
+        return response
+            .with_header("Content-Type", content_type)
+            .with_header("Content-Range", &content_range(range, len))
+            .with_reader(reader, Some(range.end - range.start));
+    }
+
+    let boundary = boundary();
+    let mut parts = Vec::new();
+    for (i, range) in ranges.iter().enumerate() {
+        // the CRLF before each boundary belongs to the boundary, not the part
+        let separator = if i == 0 { "" } else { "\r\n" };
+        let head = format!(
+            "{separator}--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {}\r\n\r\n",
+            content_range(range, len)
+        );
+        parts.push(Part::Bytes(Cursor::new(head.into_bytes())));
+        parts.push(Part::Region(range.clone()));
+    }
+    let end = format!("\r\n--{boundary}--\r\n");
+    parts.push(Part::Bytes(Cursor::new(end.into_bytes())));
+
+    let reader = FileRegions::new(file, parts);
+    let length = reader.len();
+    response
+        .with_header(
+            "Content-Type",
+            &format!("multipart/byteranges; boundary={boundary}"),
+        )
+        .with_reader(reader, Some(length))
+}
+
+fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+/// A boundary that won't turn up in the file by accident.
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    format!("byteranges_{:08x}{nanos:08x}", std::process::id())
+}
+
+enum Part {
+    Bytes(Cursor<Vec<u8>>),
+    Region(Range<u64>),
+}
+
+/// Reads parts of a file interleaved with bytes from memory, without ever
+/// holding more of the file than the caller's buffer.
+struct FileRegions {
+    file: File,
+    parts: VecDeque<Part>,
+}
+
+impl FileRegions {
+    fn new(file: File, parts: Vec<Part>) -> FileRegions {
+        FileRegions {
+            file,
+            parts: parts.into(),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.get_ref().len() as u64,
+                Part::Region(range) => range.end - range.start,
+            })
+            .sum()
+    }
+}
+
+impl Read for FileRegions {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(part) = self.parts.front_mut() {
+            let n = match part {
+                Part::Bytes(bytes) => bytes.read(buf)?,
+                Part::Region(range) if range.is_empty() => 0,
+                Part::Region(range) => {
+                    let max = buf.len().min((range.end - range.start) as usize);
+                    self.file.seek(SeekFrom::Start(range.start))?;
+                    let n = self.file.read(&mut buf[..max])?;
+                    if n == 0 {
+                        // the file shrank after we looked at its size
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    range.start += n as u64;
+                    n
+                }
+            };
+            if n > 0 {
+                return Ok(n);
+            }
+            self.parts.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parses_range_specs() {
+        let bounds = |header| {
+            let ranges = parse(header, 1000).unwrap();
+            ranges.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(bounds("bytes=0-499"), [(0, 500)]);
+        assert_eq!(bounds("bytes=-200, 900-"), [(800, 1000)]);
+        assert_eq!(bounds("bytes=0-1,4-5, 2-3, 9-9"), [(0, 6), (9, 10)]);
+        assert_eq!(bounds("bytes=500-5000"), [(500, 1000)]);
+
+        // unsatisfiable
+        assert_eq!(parse("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(parse("bytes=-0", 1000), Some(vec![]));
+
+        // ignored
+        assert_eq!(parse("items=0-1", 1000), None);
+        assert_eq!(parse("bytes=5-1", 1000), None);
+        assert_eq!(parse("bytes=a-b", 1000), None);
+    }
+
+    #[test]
+    fn streams_multipart_byteranges() {
+        let path = std::env::temp_dir().join(format!("web_server_range_{}", std::process::id()));
+        fs::write(&path, b"0123456789").unwrap();
+
+        let response = partial_file(File::open(&path).unwrap(), &[1..3, 7..10], 10, "text/plain");
+        let boundary = response
+            .headers
+            .get("Content-Type")
+            .unwrap()
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let length = response.body.len().unwrap();
+        let mut out = Vec::new();
+        response.write_to(&mut out, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let body = out.split_once("\r\n\r\n").unwrap().1;
+
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 1-2/10\r\n\r\n12\r\n\
+             --{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 7-9/10\r\n\r\n789\r\n\
+             --{boundary}--\r\n"
+        );
+        assert_eq!(body, expected);
+        assert_eq!(length, expected.len() as u64);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn checks_if_range() {
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(784_111_777);
+        let holds = |condition: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nIf-Range: {condition}\r\n\r\n");
+            let request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            if_range_holds(&request, "\"abc\"", modified)
+        };
+        assert!(holds("\"abc\""));
+        assert!(holds("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!holds("W/\"abc\""));
+        assert!(!holds("Sun, 06 Nov 1994 08:49:38 GMT"));
+        assert!(!holds("yesterday"));
+        assert!(!holds("Sun, 06 Nov 99999999999999 08:49:37 GMT"));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    middleware::Middleware,
+    request::{normalize_path, Request},
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Once this many buckets exist, full ones are dropped, as a full bucket
+/// behaves exactly like a missing one. If that isn't enough, the least
+/// recently used go too, down to `MAX_BUCKETS_AFTER_PRUNING`, so the next
+/// pruning is thousands of new clients away.
+const MAX_BUCKETS: usize = 10_000;
+const MAX_BUCKETS_AFTER_PRUNING: usize = MAX_BUCKETS * 3 / 4;
+
+/// `requests` per `period`, which may all come at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Rate {
+    pub fn per_second(requests: u32) -> Rate {
+        Rate {
+            requests,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    pub fn per_minute(requests: u32) -> Rate {
+        Rate {
+            requests,
+            period: Duration::from_secs(60),
+        }
+    }
+
+    fn tokens_per_second(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    /// `10/s`, `100/m` or `1000/h`.
+    fn from_str(s: &str) -> Result<Rate, String> {
+        let invalid = || format!("`{s}` is not a rate like 10/s, 100/m or 1000/h");
+        let (requests, unit) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let seconds = match unit.trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        if requests == 0 {
+            return Err(invalid());
+        }
+        Ok(Rate {
+            requests,
+            period: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// Tokens trickle in at the rate's pace up to `requests`; each request takes one.
+#[derive(Debug)]
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+    /// When a request last asked for a token.
+    used: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Bucket {
+        Bucket {
+            rate,
+            tokens: f64::from(rate.requests),
+            updated: now,
+            used: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let tokens = self.tokens + elapsed * self.rate.tokens_per_second();
+        self.tokens = tokens.min(f64::from(self.rate.requests));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.rate.requests)
+    }
+
+    /// How long until the next token, zero if there is one.
+    fn wait(&self) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.rate.tokens_per_second())
+    }
+}
+
+/// Token-bucket rate limiting per client IP, with optional tighter limits
+/// for routes below a path prefix. Requests over the limit get 429 and a
+/// `Retry-After` saying when to come back.
+///
+/// A route limit is counted separately from the overall one, and a request
+/// has to fit both.
+pub struct RateLimit {
+    /// `None` for no overall limit.
+    rate: Option<Rate>,
+    routes: Vec<(String, Rate)>,
+    /// Keyed by the route's index, `None` for the overall limit.
+    buckets: Mutex<HashMap<(Option<usize>, IpAddr), Bucket>>,
+}
+
+impl RateLimit {
+    /// Limits every client to `rate` across all routes.
+    pub fn new(rate: Rate) -> RateLimit {
+        RateLimit {
+            rate: Some(rate),
+            ..RateLimit::unlimited()
+        }
+    }
+
+    /// No overall limit, only the route limits added later.
+    pub fn unlimited() -> RateLimit {
+        RateLimit {
+            rate: None,
+            routes: Vec::new(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limits every client to `rate` for paths starting with `prefix`.
+    pub fn route(mut self, prefix: &str, rate: Rate) -> RateLimit {
+        self.routes.push((prefix.to_string(), rate));
+        self
+    }
+
+    /// Takes a token from every bucket that applies, or returns the longest
+    /// wait. Tokens are only taken when all buckets have one.
+    fn check(&self, ip: IpAddr, path: &str, now: Instant) -> Result<(), Duration> {
+        let mut limits: Vec<(Option<usize>, Rate)> =
+            self.rate.map(|r| (None, r)).into_iter().collect();
+        // the path the handlers serve, so `/%64ownloads` counts as `/downloads`
+        let path = normalize_path(path);
+        for (i, (prefix, rate)) in self.routes.iter().enumerate() {
+            if path.starts_with(prefix.as_str()) {
+                limits.push((Some(i), *rate));
+            }
+        }
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_BUCKETS {
+            prune(&mut buckets, now);
+        }
+
+        let mut wait = Duration::ZERO;
+        for &(route, rate) in &limits {
+            let bucket = buckets
+                .entry((route, ip))
+                .or_insert_with(|| Bucket::new(rate, now));
+            bucket.refill(now);
+            bucket.used = now;
+            wait = wait.max(bucket.wait());
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for &(route, _) in &limits {
+            if let Some(bucket) = buckets.get_mut(&(route, ip)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drops the full buckets, then the least recently used ones until at most
+/// `MAX_BUCKETS_AFTER_PRUNING` are left. A client whose bucket was dropped
+/// early starts over with a full one; under that many clients the oldest
+/// are the least likely to come back.
+fn prune(buckets: &mut HashMap<(Option<usize>, IpAddr), Bucket>, now: Instant) {
+    buckets.retain(|_, bucket| {
+        bucket.refill(now);
+        !bucket.is_full()
+    });
+    let Some(excess) = buckets.len().checked_sub(MAX_BUCKETS_AFTER_PRUNING) else {
+        return;
+    };
+    let mut by_use: Vec<(Instant, (Option<usize>, IpAddr))> =
+        buckets.iter().map(|(key, b)| (b.used, *key)).collect();
+    by_use.select_nth_unstable_by_key(excess, |(used, _)| *used);
+    for (_, key) in &by_use[..excess] {
+        buckets.remove(key);
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: &mut Request, next: &dyn Handler) -> Response {
+        let Some(addr) = request.remote_addr else {
+            return next.handle(request);
+        };
+        match self.check(addr.ip(), &request.path, Instant::now()) {
+            Ok(()) => next.handle(request),
+            Err(wait) => {
+                // whole seconds, rounded up so the client doesn't come back too early
+                let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Response::error(StatusCode::TooManyRequests)
+                    .with_header("Retry-After", &seconds.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!("10/s".parse(), Ok(Rate::per_second(10)));
+        assert_eq!("120/m".parse(), Ok(Rate::per_minute(120)));
+        assert!("0/s".parse::<Rate>().is_err());
+        assert!("10/d".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn limits_each_client_and_route() {
+        let limit = RateLimit::new(Rate::per_second(3)).route("/sleep", Rate::per_minute(1));
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+
+        assert_eq!(limit.check(a, "/sleep", start), Ok(()));
+        let wait = limit.check(a, "/sleep", start).unwrap_err();
+        assert_eq!(wait.as_secs(), 60);
+        // the refused request took no overall token
+        assert_eq!(limit.check(a, "/", start), Ok(()));
+        assert_eq!(limit.check(a, "/", start), Ok(()));
+        assert!(limit.check(a, "/", start).is_err());
+
+        // other clients have their own buckets
+        assert_eq!(limit.check(b, "/sleep", start), Ok(()));
+
+        // a third of a second brings back one of the three tokens per second
+        let later = start + Duration::from_millis(334);
+        assert_eq!(limit.check(a, "/", later), Ok(()));
+        assert!(limit.check(a, "/", later).is_err());
+    }
+
+    #[test]
+    fn matches_routes_on_the_decoded_path() {
+        let limit = RateLimit::unlimited().route("/downloads/", Rate::per_minute(1));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limit.check(ip, "/downloads/a.zip", now), Ok(()));
+        for path in [
+            "/%64ownloads/a.zip",
+            "/./downloads/b.zip",
+            "/x/../downloads/c.zip",
+            "/downloads/../downloads/d.zip",
+            "//downloads/",
+            "/downloads/",
+        ] {
+            assert!(limit.check(ip, path, now).is_err(), "{path}");
+        }
+        assert_eq!(limit.check(ip, "/downloadsx", now), Ok(()));
+    }
+
+    #[test]
+    fn forgets_the_least_recently_used_clients() {
+        let limit = RateLimit::unlimited().route("/", Rate::per_minute(1));
+        let ip = |i: usize| IpAddr::from((i as u32).to_be_bytes());
+        let start = Instant::now();
+        let at = |i: usize| start + Duration::from_micros(i as u64);
+
+        for i in 0..MAX_BUCKETS {
+            assert_eq!(limit.check(ip(i), "/", at(i)), Ok(()));
+        }
+        // every bucket is empty, none can be dropped for being full
+        assert_eq!(limit.check(ip(MAX_BUCKETS), "/", at(MAX_BUCKETS)), Ok(()));
+        assert_eq!(
+            limit.buckets.lock().unwrap().len(),
+            MAX_BUCKETS_AFTER_PRUNING + 1
+        );
+
+        let later = at(MAX_BUCKETS + 1);
+        // the most recent clients are still limited, the oldest start over
+        assert!(limit.check(ip(MAX_BUCKETS - 1), "/", later).is_err());
+        assert_eq!(limit.check(ip(0), "/", later), Ok(()));
+    }
+}
@@ -0,0 +1,832 @@
+use crate::response::StatusCode;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+    net::SocketAddr,
+    sync::{Mutex, Weak},
+};
+
+/// Upper bounds applied while reading a request, so a misbehaving client
+/// can't make a worker buffer an unbounded amount of data.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_line_len: usize,
+    pub max_headers: usize,
+    pub max_body_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_line_len: 8 * 1024,
+            max_headers: 100,
+            max_body_len: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Connect,
+    Trace,
+}
+
+impl Method {
+    pub fn parse(s: &str) -> Option<Method> {
+        match s {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "OPTIONS" => Some(Method::Options),
+            "PATCH" => Some(Method::Patch),
+            "CONNECT" => Some(Method::Connect),
+            "TRACE" => Some(Method::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// Header list that keeps the order and spelling the client sent, but looks
+/// names up case-insensitively as RFC 9110 requires.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Returns the first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a value, keeping any values already present for `name`.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Replaces every value of `name` with `value`.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    /// True if the comma separated header `name` lists `token`, e.g.
+    /// `Connection: keep-alive, Upgrade` has the token `upgrade`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    /// The body, read in full before the handler runs; empty for handlers
+    /// that stream it, which read it from `body_reader` instead.
+    pub body: Vec<u8>,
+    /// Path parameters filled in by the router, e.g. `id` for `/users/:id`.
+    pub params: Vec<(String, String)>,
+    /// The client's address, filled in by the server.
+    pub remote_addr: Option<SocketAddr>,
+    framing: Framing,
+    stream: Option<BodyReader>,
+}
+
+impl Clone for Request {
+    /// A body that is still being streamed stays with the original.
+    fn clone(&self) -> Request {
+        Request {
+            method: self.method,
+            path: self.path.clone(),
+            query: self.query.clone(),
+            version: self.version,
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            params: self.params.clone(),
+            remote_addr: self.remote_addr,
+            framing: self.framing,
+            stream: None,
+        }
+    }
+}
+
+impl Request {
+    /// Reads one request from `reader`: request line, headers and a body
+    /// framed by `Content-Length` or `Transfer-Encoding: chunked`.
+    pub fn read_from<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+        let mut request = Request::read_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// Reads the request line and headers, leaving the body in `reader`.
+    pub(crate) fn read_head<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut line = read_line(reader, limits.max_line_len, ParseError::UriTooLong)?;
+        // RFC 9112 asks servers to ignore empty lines before the request line
+        while line.is_empty() {
+            line = read_line(reader, limits.max_line_len, ParseError::UriTooLong)?;
+        }
+
+        let mut parts = line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(m), Some(t), Some(v), None) if !t.is_empty() => (m, t, v),
+                _ => return Err(ParseError::BadRequestLine),
+            };
+
+        let method = Method::parse(method).ok_or(ParseError::UnknownMethod)?;
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+            _ => return Err(ParseError::BadRequestLine),
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target.to_string(), None),
+        };
+
+        let headers = read_headers(reader, limits)?;
+        let framing = framing(&headers)?;
+
+        Ok(Request {
+            method,
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            params: Vec::new(),
+            remote_addr: None,
+            framing,
+            stream: None,
+        })
+    }
+
+    /// Reads the body that follows the head into `body`.
+    pub(crate) fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<(), ParseError> {
+        self.body = match self.framing {
+            Framing::Length(length) if length > limits.max_body_len as u64 => {
+                return Err(ParseError::BodyTooLarge)
+            }
+            Framing::Length(length) => {
+                let mut body = vec![0; length as usize];
+                reader.read_exact(&mut body)?;
+                body
+            }
+            Framing::Chunked => read_chunked(reader, limits)?,
+        };
+        Ok(())
+    }
+
+    /// Leaves the body in `source` for the handler to read as it arrives.
+    pub(crate) fn stream_body(&mut self, source: Weak<Mutex<dyn BufRead + Send>>) {
+        let state = match self.framing {
+            Framing::Length(length) => BodyState::Length(length),
+            Framing::Chunked => BodyState::Chunked(Chunks::default()),
+        };
+        self.stream = Some(BodyReader { source, state });
+    }
+
+    /// Takes the body stream away once the handler has returned. False if
+    /// it wasn't read to the end, so the connection can't carry on.
+    pub(crate) fn end_body_stream(&mut self) -> bool {
+        self.stream.take().is_none_or(|stream| stream.is_done())
+    }
+
+    /// The body as it arrives from the client, for handlers that stream it
+    /// (see `Handler::streams_body`); `None` when it was read into `body`.
+    pub fn body_reader(&mut self) -> Option<&mut BodyReader> {
+        self.stream.as_mut()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The request target as sent: path plus query string.
+    pub fn target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{query}", self.path),
+            None => self.path.clone(),
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Decodes `%XX` escapes. Returns `None` for malformed escapes or if the
+/// result isn't valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// The path the file system would see: decoded, without empty or `.`
+/// segments and with each `..` taking away the segment before it. For
+/// matching configured prefixes, so that `/%61dmin`, `/./admin` or
+/// `/x/../admin` can't slip past one for `/admin`. A trailing slash is kept.
+pub(crate) fn normalize_path(path: &str) -> String {
+    let decoded = percent_decode(path).unwrap_or_else(|| path.to_string());
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && decoded.ends_with('/') {
+        normalized.push('/');
+    }
+    normalized
+}
+
+pub(crate) fn read_headers<R: BufRead>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(reader, limits.max_line_len, ParseError::HeadersTooLarge)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let (name, value) = line.split_once(':').ok_or(ParseError::BadHeader)?;
+        // whitespace between the name and the colon is forbidden, see RFC 9112 section 5.1
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+            return Err(ParseError::BadHeader);
+        }
+        headers.append(name, value.trim());
+    }
+}
+
+/// How the end of a request body is found, see RFC 9112 section 6.
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    Length(u64),
+    Chunked,
+}
+
+fn framing(headers: &Headers) -> Result<Framing, ParseError> {
+    if let Some(encoding) = headers.get("Transfer-Encoding") {
+        // chunked must be the final encoding and we don't decode anything else
+        if !encoding.trim().eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::UnsupportedTransferEncoding);
+        }
+        if headers.contains("Content-Length") {
+            return Err(ParseError::BadContentLength);
+        }
+        return Ok(Framing::Chunked);
+    }
+
+    let mut lengths = headers.get_all("Content-Length");
+    let length = match lengths.next() {
+        Some(value) => parse_number(value, 10).ok_or(ParseError::BadContentLength)?,
+        None => 0,
+    };
+    if lengths.any(|other| parse_number(other, 10) != Some(length)) {
+        return Err(ParseError::BadContentLength);
+    }
+    Ok(Framing::Length(length))
+}
+
+/// Whether a read gave up because the socket's timeout ran out.
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Parses a `Content-Length` or chunk size. Only digits are allowed: the
+/// standard parsers also take a leading `+`, which a lenient proxy in front
+/// of us might read differently.
+fn parse_number<T: TryFrom<u64>>(value: &str, radix: u32) -> Option<T> {
+    if value.is_empty() || !value.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(value, radix).ok()?.try_into().ok()
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, limits.max_line_len, ParseError::BadChunk)?;
+        // chunk extensions after ';' are allowed but we have no use for them
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size: usize = parse_number(size, 16).ok_or(ParseError::BadChunk)?;
+        if size == 0 {
+            break;
+        }
+        // the body never exceeds the limit, so this can't underflow, while
+        // `body.len() + size` would overflow on a huge chunk size
+        if size > limits.max_body_len - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if !read_line(reader, 0, ParseError::BadChunk)?.is_empty() {
+            return Err(ParseError::BadChunk);
+        }
+    }
+
+    // trailer fields are read to keep the stream in sync, then dropped
+    read_headers(reader, limits)?;
+    Ok(body)
+}
+
+/// Decodes a chunked body while it is read, for bodies that are passed on
+/// rather than buffered like `read_chunked` does.
+pub(crate) struct ChunkedReader<R> {
+    reader: R,
+    chunks: Chunks,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub(crate) fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            chunks: Chunks::default(),
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.chunks.read(&mut self.reader, buf)
+    }
+}
+
+/// Where a chunked decoder is in the body; the reader is passed to each
+/// call, so it can be one the decoder doesn't own.
+#[derive(Debug, Default)]
+struct Chunks {
+    /// Bytes left in the current chunk.
+    remaining: u64,
+    started: bool,
+    done: bool,
+}
+
+impl Chunks {
+    fn next_chunk<R: BufRead>(&mut self, reader: &mut R) -> io::Result<()> {
+        let limits = Limits::default();
+        let mut line =
+            || read_line(reader, limits.max_line_len, ParseError::BadChunk).map_err(invalid_data);
+        // the CRLF that ends the previous chunk's data
+        if self.started && !line()?.is_empty() {
+            return Err(invalid_data("malformed chunked body"));
+        }
+        self.started = true;
+
+        let size_line = line()?;
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        self.remaining =
+            parse_number(size, 16).ok_or_else(|| invalid_data("malformed chunk size"))?;
+        if self.remaining == 0 {
+            // trailer fields aren't passed on
+            read_headers(reader, &limits).map_err(invalid_data)?;
+            self.done = true;
+        }
+        Ok(())
+    }
+
+    fn read<R: BufRead>(&mut self, reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 && !self.done {
+            self.next_chunk(reader)?;
+        }
+        if self.done {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let n = reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A request body read from the connection while the handler runs, see
+/// `Request::body_reader`. It ends where the body does; `Limits::max_body_len`
+/// doesn't apply, the handler decides how much it takes. Once the handler has
+/// returned the connection is gone and reads fail.
+pub struct BodyReader {
+    /// The connection, lent by the server for as long as the handler runs.
+    source: Weak<Mutex<dyn BufRead + Send>>,
+    state: BodyState,
+}
+
+#[derive(Debug)]
+enum BodyState {
+    /// Bytes left to read.
+    Length(u64),
+    Chunked(Chunks),
+}
+
+impl BodyReader {
+    /// The length the client announced; `None` for a chunked body.
+    pub fn content_length(&self) -> Option<u64> {
+        match self.state {
+            BodyState::Length(remaining) => Some(remaining),
+            BodyState::Chunked(_) => None,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match &self.state {
+            BodyState::Length(remaining) => *remaining == 0,
+            BodyState::Chunked(chunks) => chunks.done,
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.is_done() {
+            return Ok(0);
+        }
+        let source = self
+            .source
+            .upgrade()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "the request is over"))?;
+        let mut source = source.lock().unwrap_or_else(|e| e.into_inner());
+        let mut reader: &mut (dyn BufRead + Send) = &mut *source;
+        match &mut self.state {
+            BodyState::Length(remaining) => {
+                let max = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                let n = reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *remaining -= n as u64;
+                Ok(n)
+            }
+            BodyState::Chunked(chunks) => chunks.read(&mut reader, buf),
+        }
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn invalid_data<E: Into<Box<dyn Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Reads a CRLF (or bare LF) terminated line of at most `max_len` bytes,
+/// without the line ending. Longer lines fail with `too_long`.
+pub(crate) fn read_line<R: BufRead>(
+    reader: &mut R,
+    max_len: usize,
+    too_long: ParseError,
+) -> Result<String, ParseError> {
+    let mut line = Vec::new();
+    // "+ 2" leaves room for the CRLF itself
+    let read = Read::take(&mut *reader, max_len as u64 + 2).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Err(ParseError::UnexpectedEof);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if read as u64 == max_len as u64 + 2 {
+            too_long
+        } else {
+            ParseError::UnexpectedEof
+        });
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| ParseError::BadEncoding)
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(std::io::Error),
+    UnexpectedEof,
+    BadRequestLine,
+    BadEncoding,
+    BadHeader,
+    BadContentLength,
+    BadChunk,
+    UnknownMethod,
+    UnsupportedVersion,
+    UnsupportedTransferEncoding,
+    UriTooLong,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl ParseError {
+    /// Status code to answer the client with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::UnknownMethod | ParseError::UnsupportedTransferEncoding => {
+                StatusCode::NotImplemented
+            }
+            ParseError::UnsupportedVersion => StatusCode::HttpVersionNotSupported,
+            ParseError::UriTooLong => StatusCode::UriTooLong,
+            ParseError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::BodyTooLarge => StatusCode::ContentTooLarge,
+            ParseError::Io(e) if is_timeout(e) => StatusCode::RequestTimeout,
+            _ => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "i/o error: {e}"),
+            ParseError::UnexpectedEof => f.write_str("connection closed mid-request"),
+            ParseError::BadRequestLine => f.write_str("malformed request line"),
+            ParseError::BadEncoding => f.write_str("request head is not valid UTF-8"),
+            ParseError::BadHeader => f.write_str("malformed header field"),
+            ParseError::BadContentLength => f.write_str("invalid Content-Length"),
+            ParseError::BadChunk => f.write_str("malformed chunked body"),
+            ParseError::UnknownMethod => f.write_str("unknown method"),
+            ParseError::UnsupportedVersion => f.write_str("unsupported HTTP version"),
+            ParseError::UnsupportedTransferEncoding => f.write_str("unsupported transfer encoding"),
+            ParseError::UriTooLong => f.write_str("request line too long"),
+            ParseError::HeadersTooLarge => f.write_str("too many or too large headers"),
+            ParseError::BodyTooLarge => f.write_str("body exceeds the size limit"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> ParseError {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            ParseError::UnexpectedEof
+        } else {
+            ParseError::Io(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut raw.as_bytes(), &Limits::default())
+    }
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let request =
+            parse("GET /search?q=rust HTTP/1.1\r\nHost: localhost\r\nX-Test: a\r\n\r\n").unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/search");
+        assert_eq!(request.query.as_deref(), Some("q=rust"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("X-TEST"), Some("a"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(parse(raw).unwrap().body, b"hello, world");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(
+            parse("GET /\r\n\r\n"),
+            Err(ParseError::BadRequestLine)
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nNoColon\r\n\r\n"),
+            Err(ParseError::BadHeader)
+        ));
+        for length in ["abc", "+5", "-5", "", "0x5", "5 5"] {
+            let raw = format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\nhello");
+            assert!(
+                matches!(parse(&raw), Err(ParseError::BadContentLength)),
+                "{length:?}"
+            );
+        }
+        for size in ["+a", "-1", "", "0x5"] {
+            let raw = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{size}\r\n");
+            assert!(matches!(parse(&raw), Err(ParseError::BadChunk)), "{size:?}");
+        }
+        let timed_out = ParseError::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert_eq!(timed_out.status(), StatusCode::RequestTimeout);
+        assert!(matches!(
+            parse("BREW / HTTP/1.1\r\n\r\n"),
+            Err(ParseError::UnknownMethod)
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = Limits {
+            max_line_len: 20,
+            max_headers: 1,
+            max_body_len: 4,
+        };
+        let read = |raw: &str| Request::read_from(&mut raw.as_bytes(), &limits);
+
+        assert!(matches!(
+            read("GET /a-very-long-path HTTP/1.1\r\n\r\n"),
+            Err(ParseError::UriTooLong)
+        ));
+        assert!(matches!(
+            read("GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n"),
+            Err(ParseError::HeadersTooLarge)
+        ));
+        assert!(matches!(
+            read("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello"),
+            Err(ParseError::BodyTooLarge)
+        ));
+        let limits = Limits {
+            max_body_len: 4,
+            ..Limits::default()
+        };
+        let read = |raw: &str| Request::read_from(&mut raw.as_bytes(), &limits);
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(matches!(
+            read(&format!("{chunked}3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n")),
+            Err(ParseError::BodyTooLarge)
+        ));
+        // a size that overflows when added to what was already read
+        assert!(matches!(
+            read(&format!("{chunked}3\r\nabc\r\nffffffffffffffff\r\n")),
+            Err(ParseError::BodyTooLarge)
+        ));
+        assert!(matches!(
+            read(&format!("{chunked}1ffffffffffffffff\r\n")),
+            Err(ParseError::BadChunk)
+        ));
+        assert!(matches!(
+            read(&format!("{chunked}2\r\nabX\r\n0\r\n\r\n")),
+            Err(ParseError::BadChunk)
+        ));
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let mut signed = &b"+5\r\nhello\r\n0\r\n\r\n"[..];
+        assert!(ChunkedReader::new(&mut signed)
+            .read_to_end(&mut Vec::new())
+            .is_err());
+
+        let wire = b"5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nnext";
+        let mut reader = &wire[..];
+        let mut body = String::new();
+        ChunkedReader::new(&mut reader)
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "hello, world");
+        assert_eq!(reader, b"next");
+    }
+}
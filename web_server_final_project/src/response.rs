@@ -0,0 +1,447 @@
+use crate::{date::format_http_date, request::Headers, server::Upgraded};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+const SERVER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const CHUNK_SIZE: usize = 16 * 1024;
+
+macro_rules! status_codes {
+    ($($name:ident = $code:literal, $reason:literal;)*) => {
+        /// Status codes this server uses by name. Anything else, e.g. a code
+        /// relayed from a backend, is kept as `Other`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum StatusCode {
+            $($name,)*
+            Other(u16),
+        }
+
+        impl StatusCode {
+            pub fn from_u16(code: u16) -> StatusCode {
+                match code {
+                    $($code => StatusCode::$name,)*
+                    code => StatusCode::Other(code),
+                }
+            }
+
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(StatusCode::$name => $code,)*
+                    StatusCode::Other(code) => *code,
+                }
+            }
+
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(StatusCode::$name => $reason,)*
+                    StatusCode::Other(_) => "Unknown",
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NoContent = 204, "No Content";
+    PartialContent = 206, "Partial Content";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    RequestTimeout = 408, "Request Timeout";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    UpgradeRequired = 426, "Upgrade Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+impl StatusCode {
+    /// 1xx, 204 and 304 responses never carry a body, see RFC 9112 section 6.3.
+    pub fn allows_body(&self) -> bool {
+        let code = self.as_u16();
+        code >= 200 && code != 204 && code != 304
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason())
+    }
+}
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// A body produced while it is sent, e.g. a file. Without a known length
+    /// the connection is closed to mark its end.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+}
+
+impl Body {
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body if it is already in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::Reader { length, .. } => write!(f, "Reader({length:?})"),
+        }
+    }
+}
+
+/// Takes over the connection once the response head has been sent.
+pub struct Takeover(Box<dyn FnOnce(Upgraded<'_>) + Send>);
+
+impl Takeover {
+    pub(crate) fn run(self, connection: Upgraded<'_>) {
+        (self.0)(connection)
+    }
+}
+
+impl fmt::Debug for Takeover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Takeover")
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+    takeover: Option<Takeover>,
+    length_only: bool,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+            takeover: None,
+            length_only: false,
+        }
+    }
+
+    /// A `text/plain` response.
+    pub fn text(body: impl Into<String>) -> Response {
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    pub fn html(body: impl Into<String>) -> Response {
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// A response with an already serialized JSON document as its body.
+    pub fn json(body: impl Into<String>) -> Response {
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "application/json")
+            .with_body(body.into())
+    }
+
+    /// Redirects to `location`; `status` should be one of the 3xx codes.
+    pub fn redirect(status: StatusCode, location: &str) -> Response {
+        Response::new(status)
+            .with_header("Location", location)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(format!("Redirecting to {location}\n"))
+    }
+
+    /// A short plain text response for errors: the reason phrase as body.
+    pub fn error(status: StatusCode) -> Response {
+        Response::text(format!("{}\n", status.reason())).with_status(status)
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Response {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self.length_only = false;
+        self
+    }
+
+    /// Streams the body from `reader`; pass the `length` when it is known so
+    /// the connection can be kept alive.
+    pub fn with_reader<R>(mut self, reader: R, length: Option<u64>) -> Response
+    where
+        R: Read + Send + 'static,
+    {
+        self.body = Body::Reader {
+            reader: Box::new(reader),
+            length,
+        };
+        self.length_only = false;
+        self
+    }
+
+    /// An answer to `HEAD` that only knows the `length` of the body a `GET`
+    /// would get, e.g. one passed on from another server. Middleware that
+    /// rewrites bodies leaves it alone, there is nothing to rewrite.
+    pub fn with_length_only(self, length: Option<u64>) -> Response {
+        let mut response = self.with_reader(io::empty(), length);
+        response.length_only = true;
+        response
+    }
+
+    /// Whether the body is only a length, see `with_length_only`.
+    pub fn is_length_only(&self) -> bool {
+        self.length_only
+    }
+
+    /// Switches protocols: once this `101` response has been sent, `upgrade`
+    /// gets the connection and the server is done with it when it returns.
+    pub fn with_upgrade<F>(mut self, upgrade: F) -> Response
+    where
+        F: FnOnce(Upgraded<'_>) + Send + 'static,
+    {
+        self.status = StatusCode::SwitchingProtocols;
+        self.takeover = Some(Takeover(Box::new(upgrade)));
+        self
+    }
+
+    /// Streams a body the handler writes itself: once the head has been
+    /// sent, `stream` gets the connection, and closing it ends the body.
+    /// Meant for long-lived responses like server-sent events.
+    pub fn with_stream<F>(mut self, stream: F) -> Response
+    where
+        F: FnOnce(Upgraded<'_>) + Send + 'static,
+    {
+        self.body = Body::Reader {
+            reader: Box::new(io::empty()),
+            length: None,
+        };
+        self.takeover = Some(Takeover(Box::new(stream)));
+        self
+    }
+
+    pub(crate) fn take_takeover(&mut self) -> Option<Takeover> {
+        self.takeover.take()
+    }
+
+    /// Writes the response, adding `Date`, `Server` and the framing headers.
+    /// With `head_only` the headers describe the body but it isn't sent, as a
+    /// `HEAD` request asks. Returns how many body bytes were sent.
+    ///
+    /// A body of unknown length is sent in chunks if the response has
+    /// `Transfer-Encoding: chunked`, which only HTTP/1.1 clients understand;
+    /// otherwise its end is marked by closing the connection.
+    pub fn write_to<W: Write>(self, w: &mut W, head_only: bool) -> io::Result<u64> {
+        let Response {
+            status,
+            mut headers,
+            body,
+            ..
+        } = self;
+
+        headers.insert("Date", &format_http_date(SystemTime::now()));
+        if !headers.contains("Server") {
+            headers.insert("Server", SERVER);
+        }
+        headers.remove("Content-Length");
+        let send_body = status.allows_body() && !head_only;
+        let chunked = status.allows_body()
+            && body.len().is_none()
+            && headers.has_token("Transfer-Encoding", "chunked");
+        if status.allows_body() {
+            match body.len() {
+                Some(length) => {
+                    headers.remove("Transfer-Encoding");
+                    headers.insert("Content-Length", &length.to_string());
+                }
+                None if chunked => {}
+                None => headers.insert("Connection", "close"),
+            }
+        }
+
+        // buffer the head so it usually leaves in a single packet
+        let mut head = format!("HTTP/1.1 {status}\r\n");
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        w.write_all(head.as_bytes())?;
+
+        if !send_body {
+            w.flush()?;
+            return Ok(0);
+        }
+        let sent = match body {
+            Body::Bytes(bytes) => {
+                w.write_all(&bytes)?;
+                bytes.len() as u64
+            }
+            Body::Reader {
+                reader,
+                length: Some(length),
+            } => {
+                let copied = io::copy(&mut reader.take(length), w)?;
+                // a short body would leave the client waiting for the rest
+                if copied < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                copied
+            }
+            Body::Reader {
+                reader,
+                length: None,
+            } if chunked => write_chunked(reader, w)?,
+            Body::Reader {
+                mut reader,
+                length: None,
+            } => io::copy(&mut reader, w)?,
+        };
+        w.flush()?;
+        Ok(sent)
+    }
+}
+
+/// Sends `reader` as chunks of at most `CHUNK_SIZE` bytes, each as soon as
+/// it has been read, followed by the empty last chunk.
+fn write_chunked<W: Write>(mut reader: Box<dyn Read + Send>, w: &mut W) -> io::Result<u64> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut sent = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        write!(w, "{n:x}\r\n")?;
+        w.write_all(&buf[..n])?;
+        w.write_all(b"\r\n")?;
+        // a streamed body may trickle in, don't hold back what we have
+        w.flush()?;
+        sent += n as u64;
+    }
+    w.write_all(b"0\r\n\r\n")?;
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(response: Response, head_only: bool) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out, head_only).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn frames_bytes_with_content_length() {
+        let out = serialize(Response::text("hi"), false);
+
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.contains("Date: "));
+        assert!(out.contains("Server: "));
+        assert!(out.ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn head_keeps_length_but_drops_body() {
+        let out = serialize(Response::text("hi"), true);
+
+        assert!(out.contains("Content-Length: 2\r\n"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn unknown_length_closes_connection() {
+        let response = Response::new(StatusCode::Ok).with_reader(&b"streamed"[..], None);
+        let out = serialize(response, false);
+
+        assert!(out.contains("Connection: close\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\nstreamed"));
+    }
+
+    #[test]
+    fn unknown_length_can_be_chunked() {
+        let response = Response::new(StatusCode::Ok)
+            .with_header("Transfer-Encoding", "chunked")
+            .with_reader(&b"streamed"[..], None);
+        let out = serialize(response, false);
+
+        assert!(!out.contains("Connection: close"));
+        assert!(out.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+
+        let known = serialize(
+            Response::text("hi").with_header("Transfer-Encoding", "chunked"),
+            false,
+        );
+        assert!(!known.contains("Transfer-Encoding"));
+        assert!(known.ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn not_modified_has_no_body() {
+        let out = serialize(
+            Response::text("ignored").with_status(StatusCode::NotModified),
+            false,
+        );
+
+        assert!(out.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    request::{Method, Request},
+    response::{Response, StatusCode},
+};
+use std::sync::Arc;
+
+/// Anything that can turn a request into a response. Closures taking
+/// `&mut Request` implement it, so most handlers never name this trait.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &mut Request) -> Response;
+
+    /// Whether the handler reads the body as it arrives, from
+    /// `Request::body_reader`, rather than after the server has read it into
+    /// `Request::body`. Asked once the headers are in; false by default.
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&mut Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        self(request)
+    }
+}
+
+/// Marks a handler as one that streams the request body, see
+/// `Handler::streams_body`.
+///
+/// ```no_run
+/// use std::io::Read;
+/// use web_server_final_project::{request::Request, response::Response, router::{Router, Streaming}};
+///
+/// let router = Router::new().post(
+///     "/upload",
+///     Streaming(|request: &mut Request| {
+///         let mut received = 0;
+///         if let Some(body) = request.body_reader() {
+///             received = std::io::copy(body, &mut std::io::sink()).unwrap_or(0);
+///         }
+///         Response::text(format!("{received} bytes"))
+///     }),
+/// );
+/// ```
+pub struct Streaming<H>(pub H);
+
+impl<H: Handler> Handler for Streaming<H> {
+    fn handle(&self, request: &mut Request) -> Response {
+        self.0.handle(request)
+    }
+
+    fn streams_body(&self, _request: &Request) -> bool {
+        true
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    /// Matches the rest of the path, including any further `/`.
+    Wildcard(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Arc<dyn Handler>,
+}
+
+impl Route {
+    /// HEAD is answered like GET, the body is dropped when the response is
+    /// written.
+    fn accepts(&self, method: Method) -> bool {
+        self.method == method || (method == Method::Head && self.method == Method::Get)
+    }
+
+    /// Returns the extracted parameters if `path` matches this route.
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let mut params = Vec::new();
+        let mut parts = path.trim_start_matches('/').split('/');
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Wildcard(name) => {
+                    let rest: Vec<&str> = parts.by_ref().collect();
+                    params.push((name.clone(), rest.join("/")));
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => match parts.next()? {
+                    "" => return None,
+                    value => params.push((name.clone(), value.to_string())),
+                },
+            }
+        }
+
+        match parts.next() {
+            None => Some(params),
+            _ => None,
+        }
+    }
+}
+
+/// Dispatches requests by method and path pattern.
+///
+/// Patterns are split on `/`; a segment starting with `:` captures one path
+/// segment (`/users/:id`) and a segment starting with `*` captures the rest of
+/// the path (`/files/*path`, a bare `*` is stored as `"*"`). Routes are tried
+/// in the order they were added.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Arc<dyn Handler>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Arc::new(|_: &mut Request| Response::error(StatusCode::NotFound)),
+        }
+    }
+
+    pub fn route<H>(mut self, method: Method, pattern: &str, handler: H) -> Router
+    where
+        H: Handler + 'static,
+    {
+        let segments = pattern
+            .trim_start_matches('/')
+            .split('/')
+            .map(|s| {
+                if let Some(name) = s.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = s.strip_prefix('*') {
+                    let name = if name.is_empty() { "*" } else { name };
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Literal(s.to_string())
+                }
+            })
+            .collect();
+
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler + 'static>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler + 'static>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H: Handler + 'static>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H: Handler + 'static>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Handler used when no route matches the path.
+    pub fn not_found<H: Handler + 'static>(mut self, handler: H) -> Router {
+        self.not_found = Arc::new(handler);
+        self
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &mut Request) -> Response {
+        let mut allowed: Vec<Method> = Vec::new();
+
+        for route in &self.routes {
+            let Some(params) = route.matches(&request.path) else {
+                continue;
+            };
+            if route.accepts(request.method) {
+                request.params = params;
+                return route.handler.handle(request);
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+                if route.method == Method::Get {
+                    allowed.push(Method::Head);
+                }
+            }
+        }
+
+        if allowed.is_empty() {
+            return self.not_found.handle(request);
+        }
+
+        let allow: Vec<&str> = allowed.iter().map(|m| m.as_str()).collect();
+        Response::error(StatusCode::MethodNotAllowed).with_header("Allow", &allow.join(", "))
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.routes
+            .iter()
+            .find(|route| route.accepts(request.method) && route.matches(&request.path).is_some())
+            .is_some_and(|route| route.handler.streams_body(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> Request {
+        let raw = format!("{method} {path} HTTP/1.1\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap()
+    }
+
+    fn echo_param(name: &'static str) -> impl Fn(&mut Request) -> Response {
+        move |req: &mut Request| {
+            let value = req.param(name).unwrap_or_default().to_string();
+            Response::text(value)
+        }
+    }
+
+    #[test]
+    fn extracts_path_parameters() {
+        let router = Router::new().get("/users/:id", echo_param("id"));
+
+        let response = router.handle(&mut request(Method::Get, "/users/42"));
+        assert_eq!(response.status, StatusCode::Ok);
+        assert_eq!(response.body.as_bytes(), Some(&b"42"[..]));
+
+        assert_eq!(
+            router.handle(&mut request(Method::Get, "/users")).status,
+            StatusCode::NotFound
+        );
+        assert_eq!(
+            router
+                .handle(&mut request(Method::Get, "/users/42/x"))
+                .status,
+            StatusCode::NotFound
+        );
+    }
+
+    #[test]
+    fn wildcard_captures_rest_of_path() {
+        let router = Router::new().get("/files/*path", echo_param("path"));
+
+        let response = router.handle(&mut request(Method::Get, "/files/css/site.css"));
+        assert_eq!(response.body.as_bytes(), Some(&b"css/site.css"[..]));
+    }
+
+    #[test]
+    fn asks_the_matching_route_about_streaming() {
+        let router = Router::new()
+            .get("/items", echo_param("x"))
+            .post("/items", Streaming(echo_param("x")));
+
+        assert!(router.streams_body(&request(Method::Post, "/items")));
+        assert!(!router.streams_body(&request(Method::Get, "/items")));
+        assert!(!router.streams_body(&request(Method::Post, "/other")));
+    }
+
+    #[test]
+    fn wrong_method_lists_allowed_ones() {
+        let router = Router::new()
+            .get("/items", echo_param("x"))
+            .post("/items", echo_param("x"));
+
+        let response = router.handle(&mut request(Method::Delete, "/items"));
+        assert_eq!(response.status, StatusCode::MethodNotAllowed);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD, POST"));
+    }
+}
@@ -0,0 +1,625 @@
+#[cfg(feature = "tls")]
+use crate::tls::TlsAcceptor;
+use crate::{
+    log,
+    log::{AccessEntry, AccessLog, Level},
+    middleware::panic_message,
+    request::{is_timeout, Limits, Method, Request, Version},
+    response::{Response, StatusCode},
+    router::Handler,
+    shutdown::Shutdown,
+    ThreadPool,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Shutdown as SocketShutdown, SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often blocking loops wake up to check whether a shutdown was requested.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long workers get to notice their sockets were closed at the end of
+/// the grace period.
+const STUCK_WORKER_WAIT: Duration = Duration::from_secs(1);
+
+/// A byte stream connections are served over: plain TCP, or TLS on top of it.
+pub trait Transport: Read + Write + Send {
+    /// The socket underneath, for timeouts and addresses.
+    fn tcp(&self) -> &TcpStream;
+
+    /// Called before the connection is dropped, e.g. to send TLS close_notify.
+    fn close(&mut self) {}
+}
+
+impl Transport for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+struct Listener {
+    listener: TcpListener,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<TlsAcceptor>>,
+}
+
+impl Listener {
+    fn wrap(&self, stream: TcpStream) -> io::Result<Box<dyn Transport>> {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = &self.tls {
+            return Ok(Box::new(acceptor.accept(stream)?));
+        }
+        Ok(Box::new(stream))
+    }
+
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return "https";
+        }
+        "http"
+    }
+}
+
+/// Accepts connections and hands them to a thread pool until shut down.
+pub struct Server {
+    listeners: Vec<Listener>,
+    handler: Arc<dyn Handler>,
+    workers: usize,
+    settings: ConnectionSettings,
+    shutdown: Shutdown,
+    grace_period: Duration,
+    access_log: Option<AccessLog>,
+    max_connections_per_ip: usize,
+}
+
+impl Server {
+    /// Add at least one listener before calling `run`.
+    ///
+    /// # Panics
+    ///
+    /// `run` will panic if `workers` is zero, as `ThreadPool::new` does.
+    pub fn new<H: Handler + 'static>(handler: H, workers: usize) -> Server {
+        Server {
+            listeners: Vec::new(),
+            handler: Arc::new(handler),
+            workers,
+            settings: ConnectionSettings::default(),
+            shutdown: Shutdown::new(),
+            grace_period: Duration::from_secs(30),
+            access_log: None,
+            max_connections_per_ip: 0,
+        }
+    }
+
+    /// Accepts plain HTTP connections from `listener`.
+    pub fn listener(mut self, listener: TcpListener) -> Server {
+        self.listeners.push(Listener {
+            listener,
+            #[cfg(feature = "tls")]
+            tls: None,
+        });
+        self
+    }
+
+    /// Accepts HTTPS connections from `listener`.
+    #[cfg(feature = "tls")]
+    pub fn tls_listener(mut self, listener: TcpListener, acceptor: Arc<TlsAcceptor>) -> Server {
+        self.listeners.push(Listener {
+            listener,
+            tls: Some(acceptor),
+        });
+        self
+    }
+
+    pub fn settings(mut self, settings: ConnectionSettings) -> Server {
+        self.settings = settings;
+        self
+    }
+
+    /// Flag that stops the server, e.g. `Shutdown::from_signals()`.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Server {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Records every request, see `AccessLog`.
+    pub fn access_log(mut self, access_log: AccessLog) -> Server {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// How long in-flight connections get to finish once shutdown starts.
+    pub fn grace_period(mut self, grace_period: Duration) -> Server {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// How many connections one IP address may have open at once, so a
+    /// single client can't tie up every worker. Further connections are
+    /// turned away before they reach the thread pool. 0, the default, means
+    /// no limit.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Server {
+        self.max_connections_per_ip = max;
+        self
+    }
+
+    /// Serves connections until the shutdown flag is triggered, then drains
+    /// the ones in flight and joins the workers. Connections still open after
+    /// the grace period are closed, and workers that don't finish soon after
+    /// are no longer waited for.
+    pub fn run(self) -> io::Result<()> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the server has no listeners",
+            ));
+        }
+        // non-blocking so the loop notices a shutdown without waiting for a client
+        for listener in &self.listeners {
+            listener.listener.set_nonblocking(true)?;
+            let addr = listener.listener.local_addr()?;
+            log!(Level::Info, "listening on {}://{addr}", listener.scheme());
+        }
+        let pool = ThreadPool::new(self.workers);
+        let shutdown = self.shutdown;
+        let shared = Arc::new(Shared {
+            handler: self.handler,
+            settings: self.settings,
+            shutdown: shutdown.clone(),
+            access_log: self.access_log,
+        });
+        let active: Arc<Mutex<HashMap<u64, TcpStream>>> = Arc::default();
+        let per_ip: Arc<Mutex<HashMap<IpAddr, usize>>> = Arc::default();
+        let mut next_id = 0;
+
+        while !shutdown.is_triggered() {
+            let mut idle = true;
+            for listener in &self.listeners {
+                let (stream, peer) = match listener.listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => {
+                        // e.g. the client reset before we accepted, or we ran out of file descriptors
+                        log!(Level::Warn, "failed to accept a connection: {e}");
+                        continue;
+                    }
+                };
+                idle = false;
+                let ip = peer.ip();
+                if !admit(ip, &per_ip, self.max_connections_per_ip) {
+                    log!(Level::Debug, "too many connections from {ip}");
+                    if listener.scheme() == "http" {
+                        turn_away(stream);
+                    }
+                    continue;
+                }
+                let tracked = Tracked {
+                    id: next_id,
+                    ip,
+                    active: Arc::clone(&active),
+                    per_ip: Arc::clone(&per_ip),
+                };
+                next_id += 1;
+                if stream.set_nonblocking(false).is_err() {
+                    continue;
+                }
+
+                // keep a handle so the socket can be closed from here if draining takes too long
+                if let Ok(handle) = stream.try_clone() {
+                    active.lock().unwrap().insert(tracked.id, handle);
+                }
+                let stream = match listener.wrap(stream) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log!(Level::Warn, "failed to set up a connection: {e}");
+                        continue;
+                    }
+                };
+
+                let shared = Arc::clone(&shared);
+                pool.execute(move || {
+                    // dropped when the job ends, even by a panic
+                    let _tracked = tracked;
+                    handle_connection(stream, &shared);
+                });
+            }
+            if idle {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        drop(self.listeners);
+        let in_flight = active.lock().unwrap().len();
+        log!(
+            Level::Info,
+            "shutdown requested; draining {in_flight} connections"
+        );
+
+        let deadline = Instant::now() + self.grace_period;
+        while !active.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let remaining = active.lock().unwrap();
+        for stream in remaining.values() {
+            let _ = stream.shutdown(SocketShutdown::Both);
+        }
+        log!(
+            Level::Info,
+            "drained {} connections, closed {} still open after {:?}",
+            in_flight.saturating_sub(remaining.len()),
+            remaining.len(),
+            self.grace_period
+        );
+        drop(remaining);
+
+        // workers blocked on their sockets are free now; one busy in a handler
+        // is left behind rather than holding up the exit past the deadline
+        let deadline = Instant::now() + STUCK_WORKER_WAIT;
+        while !active.lock().unwrap().is_empty() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        let stuck = active.lock().unwrap().len();
+        if stuck > 0 {
+            log!(Level::Warn, "not waiting for {stuck} stuck workers");
+            pool.detach();
+        } else {
+            drop(pool);
+        }
+        Ok(())
+    }
+}
+
+/// Counts a new connection from `ip`, unless it already has `max` (0 for no
+/// limit).
+fn admit(ip: IpAddr, per_ip: &Mutex<HashMap<IpAddr, usize>>, max: usize) -> bool {
+    let mut per_ip = per_ip.lock().unwrap();
+    let count = per_ip.entry(ip).or_insert(0);
+    if max > 0 && *count >= max {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+/// A connection's entries in the server's bookkeeping. They are removed when
+/// it is dropped, so a connection whose handling panicked doesn't count
+/// against its IP or hold up the drain at shutdown.
+struct Tracked {
+    id: u64,
+    ip: IpAddr,
+    active: Arc<Mutex<HashMap<u64, TcpStream>>>,
+    per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // never panic in here, it may run while unwinding
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.remove(&self.id);
+        drop(active);
+        let mut per_ip = self.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Tells a client over its connection cap to back off. Only what fits in the
+/// socket buffer right away is sent; the accept loop mustn't wait for anyone.
+fn turn_away(mut stream: TcpStream) {
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = stream.write_all(
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\n\
+              Connection: close\r\n\r\n",
+        );
+    }
+}
+
+/// How long connections may stay open and how much they may send.
+#[derive(Debug, Clone)]
+pub struct ConnectionSettings {
+    pub limits: Limits,
+    /// How long to wait for the next request on a kept-alive connection.
+    pub idle_timeout: Duration,
+    /// How long a client gets to send a whole request once it has started.
+    pub request_timeout: Duration,
+    /// Requests served on one connection before it is closed.
+    pub max_requests: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> ConnectionSettings {
+        ConnectionSettings {
+            limits: Limits::default(),
+            idle_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+            max_requests: 100,
+        }
+    }
+}
+
+/// A client connection. Reads stop at `deadline`, turning the socket's
+/// per-read timeout into a timeout for a whole request.
+struct Connection {
+    transport: Box<dyn Transport>,
+    deadline: Instant,
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.transport.tcp().set_read_timeout(Some(remaining))?;
+        self.transport.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+/// State every connection needs, shared between the workers.
+struct Shared {
+    handler: Arc<dyn Handler>,
+    settings: ConnectionSettings,
+    shutdown: Shutdown,
+    access_log: Option<AccessLog>,
+}
+
+/// Serves requests from `stream` until the client closes the connection,
+/// asks to close it, goes idle for too long, reaches the request cap or the
+/// server shuts down.
+fn handle_connection(stream: Box<dyn Transport>, shared: &Shared) {
+    let settings = &shared.settings;
+    // a client that stops reading shouldn't block a worker forever either
+    if stream
+        .tcp()
+        .set_write_timeout(Some(settings.request_timeout))
+        .is_err()
+    {
+        return;
+    }
+    let remote_addr = stream.tcp().peer_addr().ok();
+    let reader = BufReader::new(Connection {
+        transport: stream,
+        deadline: Instant::now(),
+    });
+
+    if let Some(reader) = serve_requests(reader, remote_addr, shared) {
+        reader.into_inner().transport.close();
+    }
+}
+
+/// Returns the connection to close, unless a handler held on to it.
+fn serve_requests(
+    mut reader: BufReader<Connection>,
+    remote_addr: Option<SocketAddr>,
+    shared: &Shared,
+) -> Option<BufReader<Connection>> {
+    let settings = &shared.settings;
+    for served in 1..=settings.max_requests {
+        if !wait_for_request(&mut reader, settings.idle_timeout, &shared.shutdown) {
+            return Some(reader);
+        }
+
+        let started = Instant::now();
+        let time = SystemTime::now();
+        reader.get_mut().deadline = started + settings.request_timeout;
+        let mut parsed = Request::read_head(&mut reader, &settings.limits);
+        if let Ok(request) = &mut parsed {
+            request.remote_addr = remote_addr;
+        }
+        let streamed = parsed
+            .as_ref()
+            .is_ok_and(|request| shared.handler.streams_body(request));
+        if !streamed {
+            parsed = parsed.and_then(|mut request| {
+                request.read_body(&mut reader, &settings.limits)?;
+                Ok(request)
+            });
+        }
+        let (mut response, keep_alive, head_only) = match &mut parsed {
+            Ok(request) if streamed => {
+                let keep_alive = wants_keep_alive(request);
+                let head_only = request.method == Method::Head;
+                // the handler reads the body through the request while it runs
+                let source = Arc::new(Mutex::new(reader));
+                request.stream_body(Arc::downgrade(&source) as Weak<Mutex<dyn BufRead + Send>>);
+                let response = shared.handler.handle(request);
+                // whatever is left of the body would be taken for the next request
+                let finished = request.end_body_stream();
+                reader = match Arc::try_unwrap(source) {
+                    Ok(source) => source.into_inner().unwrap_or_else(|e| e.into_inner()),
+                    Err(_) => {
+                        log!(
+                            Level::Warn,
+                            "a handler kept the connection to {remote_addr:?}"
+                        );
+                        return None;
+                    }
+                };
+                (response, keep_alive && finished, head_only)
+            }
+            Ok(request) => {
+                let keep_alive = wants_keep_alive(request);
+                let head_only = request.method == Method::Head;
+                (shared.handler.handle(request), keep_alive, head_only)
+            }
+            Err(e) => {
+                log!(Level::Debug, "bad request from {remote_addr:?}: {e}");
+                // the rest of the stream can't be trusted after a parse error, so close it
+                let response = Response::text(format!("{e}\n")).with_status(e.status());
+                (response, false, false)
+            }
+        };
+
+        // the handler writes to the connection itself once the head is out
+        let takeover = response.take_takeover();
+        // HTTP/1.1 clients take a body of unknown length in chunks, for older
+        // ones its end is marked by closing the connection
+        let chunked = takeover.is_none()
+            && response.body.len().is_none()
+            && parsed
+                .as_ref()
+                .is_ok_and(|request| request.version == Version::Http11);
+        if chunked {
+            response.headers.insert("Transfer-Encoding", "chunked");
+        }
+        let keep_alive = keep_alive
+            && takeover.is_none()
+            && (response.body.len().is_some() || chunked)
+            && served < settings.max_requests
+            && !shared.shutdown.is_triggered()
+            && !response.headers.has_token("Connection", "close");
+        // a protocol switch keeps the handler's `Connection: upgrade`
+        if response.status != StatusCode::SwitchingProtocols {
+            let connection = if keep_alive { "keep-alive" } else { "close" };
+            response.headers.insert("Connection", connection);
+        }
+
+        let status = response.status.as_u16();
+        let written = response.write_to(reader.get_mut(), head_only);
+
+        if let Some(access_log) = &shared.access_log {
+            let request = parsed.as_ref().ok();
+            let target = request.map(|r| r.target());
+            access_log.record(&AccessEntry {
+                remote_addr,
+                time,
+                method: request.map_or("-", |r| r.method.as_str()),
+                target: target.as_deref().unwrap_or("-"),
+                version: request.map_or("-", |r| r.version.as_str()),
+                status,
+                bytes: *written.as_ref().unwrap_or(&0),
+                duration: started.elapsed(),
+                referer: request.and_then(|r| r.header("Referer")),
+                user_agent: request.and_then(|r| r.header("User-Agent")),
+            });
+        }
+
+        if let Some(takeover) = takeover {
+            if written.is_ok() && !head_only {
+                // out of `Recover`'s reach; the connection just ends
+                let upgraded = Upgraded {
+                    reader: &mut reader,
+                    shutdown: &shared.shutdown,
+                };
+                let run = panic::catch_unwind(AssertUnwindSafe(|| takeover.run(upgraded)));
+                if let Err(payload) = run {
+                    let target = parsed.as_ref().map(|r| r.target()).unwrap_or_default();
+                    log!(
+                        Level::Error,
+                        "connection handler for {target} panicked: {}",
+                        panic_message(&*payload)
+                    );
+                }
+            }
+            return Some(reader);
+        }
+        // the client may already be gone, there is nobody to report a failed write to
+        if written.is_err() || !keep_alive {
+            return Some(reader);
+        }
+    }
+    Some(reader)
+}
+
+/// A connection handed over to a handler after the response head: to speak
+/// another protocol after `101 Switching Protocols`, or to stream a body of
+/// its own. Reads see any bytes the client sent right after its request.
+pub struct Upgraded<'a> {
+    reader: &'a mut BufReader<Connection>,
+    shutdown: &'a Shutdown,
+}
+
+impl Upgraded<'_> {
+    /// Limits how long the reads from now on may take, together.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.reader.get_mut().deadline = Instant::now() + timeout;
+    }
+
+    /// Waits up to `timeout` for the client to send something. Returns false
+    /// when it didn't, an `UnexpectedEof` error when it closed the connection.
+    pub fn wait_for_data(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.set_read_timeout(timeout);
+        match self.reader.fill_buf() {
+            Ok([]) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(true),
+            Err(e) if is_timeout(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the server is shutting down and the connection should end.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+}
+
+impl Read for Upgraded<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Upgraded<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+/// Waits until the next request starts arriving. Returns false if the
+/// connection closed, stayed idle for `idle_timeout` or the server is
+/// shutting down; all normal ways for a kept-alive connection to end.
+fn wait_for_request(
+    reader: &mut BufReader<Connection>,
+    idle_timeout: Duration,
+    shutdown: &Shutdown,
+) -> bool {
+    let idle_deadline = Instant::now() + idle_timeout;
+    loop {
+        if shutdown.is_triggered() {
+            return false;
+        }
+        // wake up regularly so a shutdown doesn't wait for the idle timeout
+        let now = Instant::now();
+        reader.get_mut().deadline = idle_deadline.min(now + POLL_INTERVAL);
+
+        match reader.fill_buf() {
+            Ok(buf) => return !buf.is_empty(),
+            Err(e) if is_timeout(&e) && now < idle_deadline => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// HTTP/1.1 connections persist unless closed explicitly, HTTP/1.0 ones only
+/// when the client opts in.
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Set from the signal handler; a plain static because that is all a signal
+/// handler can safely touch.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+// values shared by Linux, the BSDs, macOS and the Windows C runtime
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+extern "C" fn on_signal(_signum: i32) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+/// A cloneable flag telling the server to stop accepting connections and
+/// wind down the ones in flight.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    flag: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Installs SIGINT and SIGTERM handlers and returns a flag that is also
+    /// triggered by them.
+    pub fn from_signals() -> Shutdown {
+        // SAFETY: `on_signal` only stores to an atomic, which is async-signal-safe
+        unsafe {
+            signal(SIGINT, on_signal);
+            signal(SIGTERM, on_signal);
+        }
+
+        Shutdown {
+            flag: Arc::new(AtomicBool::new(false)),
+            signals: true,
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.flag.load(Ordering::SeqCst) || (self.signals && SIGNALLED.load(Ordering::SeqCst))
+    }
+}
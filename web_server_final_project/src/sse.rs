@@ -0,0 +1,202 @@
+use crate::{
+    request::Request,
+    response::{Response, StatusCode},
+    server::{Upgraded, POLL_INTERVAL},
+};
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// One server-sent event. Multi-line data is split over several `data:`
+/// lines, which the browser joins back with newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+    /// How long the browser should wait before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// The event type, `message` when not set.
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// The browser sends the last id it saw as `Last-Event-ID` on reconnect.
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event in the `text/event-stream` format, blank line included.
+    pub fn to_wire(&self) -> String {
+        // a line break would end the field early and let the value inject fields
+        let single_line = |value: &str| value.replace(['\r', '\n'], " ");
+
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            let _ = writeln!(out, "event: {}", single_line(event));
+        }
+        if let Some(id) = &self.id {
+            // a NUL makes browsers ignore the id
+            let _ = writeln!(out, "id: {}", single_line(id).replace('\0', ""));
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(out, "retry: {}", retry.as_millis());
+        }
+        for line in self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+            let _ = writeln!(out, "data: {line}");
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Answers with an event stream; `handler` then runs on the worker thread and
+/// sends events until it returns, the client goes away or the server shuts
+/// down.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use web_server_final_project::{request::Request, sse::{self, Event}};
+///
+/// let progress = |req: &mut Request| {
+///     sse::stream(req, |events| {
+///         let mut step: u32 = events.last_event_id().and_then(|id| id.parse().ok()).unwrap_or(0);
+///         while step < 100 && events.wait(Duration::from_secs(1)) {
+///             step += 1;
+///             let event = Event::new(format!("{step}%")).with_id(step.to_string());
+///             if events.send(&event).is_err() {
+///                 break;
+///             }
+///         }
+///     })
+/// };
+/// ```
+pub fn stream<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce(&mut EventStream) + Send + 'static,
+{
+    let last_event_id = request.header("Last-Event-ID").map(String::from);
+    Response::new(StatusCode::Ok)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_stream(move |io| {
+            let mut events = EventStream {
+                io,
+                last_event_id,
+                heartbeat: Duration::from_secs(15),
+                last_write: Instant::now(),
+            };
+            handler(&mut events);
+        })
+}
+
+/// The open connection of an event stream.
+pub struct EventStream<'a> {
+    io: Upgraded<'a>,
+    last_event_id: Option<String>,
+    heartbeat: Duration,
+    last_write: Instant,
+}
+
+impl EventStream<'_> {
+    /// The id of the last event the client got before it reconnected, so the
+    /// stream can resume after it.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// How long the stream may stay silent before `wait` sends a comment to
+    /// keep proxies from timing it out. 15 seconds by default.
+    pub fn set_heartbeat(&mut self, heartbeat: Duration) {
+        self.heartbeat = heartbeat;
+    }
+
+    pub fn send(&mut self, event: &Event) -> io::Result<()> {
+        self.write(event.to_wire().as_bytes())
+    }
+
+    /// Sends a comment line, which clients ignore.
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        let text = text.replace(['\r', '\n'], " ");
+        self.write(format!(": {text}\n\n").as_bytes())
+    }
+
+    /// Waits for `duration`, sending heartbeats as they fall due. Returns
+    /// false as soon as the stream should end: the client disconnected or the
+    /// server is shutting down.
+    pub fn wait(&mut self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if self.io.is_shutting_down() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            if now >= self.last_write + self.heartbeat && self.comment("heartbeat").is_err() {
+                return false;
+            }
+
+            let next_heartbeat = self.last_write + self.heartbeat;
+            let wake = deadline.min(next_heartbeat).min(now + POLL_INTERVAL);
+            // the client has nothing to say, reading only tells us when it hangs up
+            match self.io.wait_for_data(wake.saturating_duration_since(now)) {
+                Ok(false) => {}
+                Ok(true) => {
+                    let mut discard = [0; 512];
+                    if !matches!(self.io.read(&mut discard), Ok(n) if n > 0) {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.io.write_all(bytes)?;
+        self.io.flush()?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_events() {
+        let event = Event::new("line one\nline two")
+            .with_event("progress")
+            .with_id("7")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            event.to_wire(),
+            "event: progress\nid: 7\nretry: 3000\ndata: line one\ndata: line two\n\n"
+        );
+
+        let sneaky = Event::new("").with_event("a\ndata: injected");
+        assert_eq!(sneaky.to_wire(), "event: a data: injected\ndata: \n\n");
+    }
+}
@@ -0,0 +1,515 @@
+use crate::{
+    cache::{self, FileCache},
+    compression,
+    date::format_http_date,
+    range,
+    request::{percent_decode, Method, Request},
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Serves files below a document root.
+///
+/// When mounted on a wildcard route (`/static/*`) the captured part of the
+/// path is looked up, otherwise the whole request path is.
+pub struct StaticFiles {
+    root: PathBuf,
+    precompressed: bool,
+    cache: Option<FileCache>,
+    listings: bool,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            precompressed: true,
+            cache: None,
+            listings: false,
+        }
+    }
+
+    /// Keeps file contents in `cache` instead of reading them on every request.
+    pub fn with_cache(mut self, cache: FileCache) -> StaticFiles {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Whether to send `file.gz` in place of `file` to clients that accept
+    /// gzip, when it exists. On by default.
+    pub fn with_precompressed(mut self, precompressed: bool) -> StaticFiles {
+        self.precompressed = precompressed;
+        self
+    }
+
+    /// Whether a directory without an `index.html` gets a generated page
+    /// listing its contents; otherwise it is a 404. Off by default.
+    pub fn with_listings(mut self, listings: bool) -> StaticFiles {
+        self.listings = listings;
+        self
+    }
+
+    /// The `.gz` sibling of `path`, if there is one.
+    fn precompressed_variant(&self, path: &Path) -> Option<PathBuf> {
+        if !self.precompressed {
+            return None;
+        }
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".gz");
+        let gz = path.with_file_name(name);
+        gz.is_file().then_some(gz)
+    }
+
+    /// Maps a URL path to a file below the root, refusing anything that could
+    /// escape it.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(url_path)?;
+        let mut path = self.root.clone();
+
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                // backslashes are separators on Windows and NUL truncates paths in C APIs
+                s if s.contains(['\\', '\0']) => return None,
+                s => path.push(s),
+            }
+        }
+
+        Some(path)
+    }
+
+    /// Sends the file at `path`, the parts of it a `Range` header asks for,
+    /// or a 304 when the client's copy is current.
+    pub fn serve_file(&self, request: &Request, path: &Path) -> Response {
+        // the plain file is a variant too once a `.gz` sibling exists
+        let gzipped = self.precompressed_variant(path);
+        let varies = gzipped.is_some();
+        let gzipped =
+            gzipped.filter(|_| compression::accepted_quality(&request.headers, "gzip") > 0.0);
+        let encoded = gzipped.is_some();
+        let file_path = gzipped.as_deref().unwrap_or(path);
+        let metadata = match fs::metadata(file_path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return self.not_found(),
+            Err(e) => return self.read_error(e),
+        };
+        let len = metadata.len();
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let etag = cache::etag(len, modified);
+
+        let mut headers = vec![("ETag", etag.clone())];
+        if varies {
+            headers.push(("Vary", "Accept-Encoding".to_string()));
+        }
+        if cache::not_modified(request, &etag, modified) {
+            return with_headers(Response::new(StatusCode::NotModified), headers);
+        }
+        if encoded {
+            headers.push(("Content-Encoding", "gzip".to_string()));
+        }
+        headers.push(("Last-Modified", format_http_date(modified)));
+        headers.push(("Accept-Ranges", "bytes".to_string()));
+
+        let ranges = match request.header("Range") {
+            Some(value)
+                if request.method == Method::Get
+                    && range::if_range_holds(request, &etag, modified) =>
+            {
+                range::parse(value, len)
+            }
+            _ => None,
+        };
+        let response = match ranges {
+            Some(ranges) if ranges.is_empty() => return range::not_satisfiable(len),
+            // ranges are read straight from the file, large downloads are what they are for
+            Some(ranges) => match File::open(file_path) {
+                Ok(file) => range::partial_file(file, &ranges, len, content_type(path)),
+                Err(e) => return self.read_error(e),
+            },
+            None => match self.body(file_path, len) {
+                Ok(response) => response.with_header("Content-Type", content_type(path)),
+                Err(e) => return self.read_error(e),
+            },
+        };
+        with_headers(response, headers)
+    }
+
+    /// The whole file as a 200 response: from the cache when it fits there,
+    /// otherwise streamed from disk.
+    fn body(&self, path: &Path, len: u64) -> io::Result<Response> {
+        let response = Response::new(StatusCode::Ok);
+        match &self.cache {
+            Some(cache) if len <= cache.max_file_size() => {
+                let file = cache.get(path)?;
+                Ok(response.with_body(file.contents.clone()))
+            }
+            _ => Ok(response.with_reader(File::open(path)?, Some(len))),
+        }
+    }
+
+    fn read_error(&self, error: io::Error) -> Response {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Response::error(StatusCode::Forbidden),
+            _ => self.not_found(),
+        }
+    }
+
+    /// An HTML page listing `dir`, sorted by the `sort` (`name`, `size` or
+    /// `modified`) and `order` (`asc` or `desc`) query parameters. Hidden
+    /// files are left out.
+    fn listing(&self, request: &Request, dir: &Path) -> Response {
+        let read = match fs::read_dir(dir) {
+            Ok(read) => read,
+            Err(e) => return self.read_error(e),
+        };
+        let mut entries: Vec<Entry> = read
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                if name.starts_with('.') {
+                    return None;
+                }
+                // follows symlinks, like serving the file would
+                let metadata = fs::metadata(entry.path()).ok()?;
+                Some(Entry {
+                    name,
+                    is_dir: metadata.is_dir(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+                })
+            })
+            .collect();
+
+        let params = request.query_params();
+        let sort = params
+            .get("sort")
+            .filter(|sort| matches!(*sort, "size" | "modified"))
+            .unwrap_or("name");
+        let descending = params.get("order") == Some("desc");
+        entries.sort_by(|a, b| {
+            let order = match sort {
+                "size" => a.size.cmp(&b.size),
+                "modified" => a.modified.cmp(&b.modified),
+                _ => a.name.cmp(&b.name),
+            };
+            let order = if descending { order.reverse() } else { order };
+            // directories stay on top whichever way the files are sorted
+            b.is_dir.cmp(&a.is_dir).then(order)
+        });
+
+        let title = html_escape(&percent_decode(&request.path).unwrap_or_default());
+        let mut page = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Index of {title}</title>\n</head>\n<body>\n<h1>Index of {title}</h1>\n\
+             <table>\n<tr>"
+        );
+        for (column, label) in [
+            ("name", "Name"),
+            ("size", "Size"),
+            ("modified", "Last modified"),
+        ] {
+            // clicking the current column again flips the order
+            let order = if column == sort && !descending {
+                "desc"
+            } else {
+                "asc"
+            };
+            page.push_str(&format!(
+                "<th><a href=\"?sort={column}&amp;order={order}\">{label}</a></th>"
+            ));
+        }
+        page.push_str("</tr>\n");
+        if request.path != "/" {
+            page.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+        }
+        for entry in &entries {
+            let slash = if entry.is_dir { "/" } else { "" };
+            let size = if entry.is_dir {
+                "-".to_string()
+            } else {
+                human_size(entry.size)
+            };
+            page.push_str(&format!(
+                "<tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{size}</td><td>{}</td></tr>\n",
+                html_escape(&percent_encode(&entry.name)),
+                html_escape(&entry.name),
+                format_http_date(entry.modified),
+            ));
+        }
+        page.push_str("</table>\n</body>\n</html>\n");
+        Response::html(page)
+    }
+
+    /// Uses `404.html` from the root as the error page when there is one.
+    fn not_found(&self) -> Response {
+        match fs::read(self.root.join("404.html")) {
+            Ok(page) => Response::new(StatusCode::NotFound)
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_body(page),
+            Err(_) => Response::error(StatusCode::NotFound),
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &mut Request) -> Response {
+        let url_path = request.param("*").unwrap_or(&request.path);
+        let Some(mut path) = self.resolve(url_path) else {
+            return Response::error(StatusCode::Forbidden);
+        };
+
+        if path.is_dir() {
+            // without the trailing slash relative links in the index would resolve one level up
+            if !request.path.ends_with('/') {
+                let location = format!("{}/", request.path);
+                return Response::redirect(StatusCode::MovedPermanently, &location);
+            }
+            let index = path.join("index.html");
+            if self.listings && !index.exists() {
+                return self.listing(request, &path);
+            }
+            path = index;
+        }
+
+        self.serve_file(request, &path)
+    }
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Makes `text` safe to put between tags and inside quoted attributes.
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a file name for use as one segment of a relative URL.
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// `1536` as `1.5 KiB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn with_headers(mut response: Response, headers: Vec<(&str, String)>) -> Response {
+    for (name, value) in headers {
+        response.headers.insert(name, &value);
+    }
+    response
+}
+
+/// Guesses the media type from the file extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("mp4") => "video/mp4",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_below_root() {
+        let files = StaticFiles::new("public");
+
+        assert_eq!(
+            files.resolve("/css/site%20main.css"),
+            Some(PathBuf::from("public/css/site main.css"))
+        );
+        assert_eq!(files.resolve("/./a//b"), Some(PathBuf::from("public/a/b")));
+    }
+
+    #[test]
+    fn lists_directories_without_an_index() {
+        let root = std::env::temp_dir().join(format!("web_server_listing_{}", std::process::id()));
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::write(root.join("a <b>.txt"), vec![0; 2048]).unwrap();
+        fs::write(root.join("z.txt"), "z").unwrap();
+        fs::write(root.join(".secret"), "").unwrap();
+        let files = StaticFiles::new(&root).with_listings(true);
+
+        let list = |target: &str| {
+            let raw = format!("GET {target} HTTP/1.1\r\nHost: x\r\n\r\n");
+            let mut request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            let response = files.handle(&mut request);
+            String::from_utf8(response.body.as_bytes().unwrap().to_vec()).unwrap()
+        };
+        let page = list("/");
+        assert!(page.contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a></td><td>2.0 KiB"));
+        assert!(!page.contains("secret"));
+        let order = |page: &str| {
+            let names = ["sub%20dir/", "a%20%3Cb%3E.txt", "z.txt"];
+            let mut found: Vec<(usize, &str)> =
+                names.iter().map(|n| (page.find(n).unwrap(), *n)).collect();
+            found.sort();
+            found.into_iter().map(|(_, n)| n).collect::<Vec<_>>()
+        };
+        assert_eq!(order(&page), ["sub%20dir/", "a%20%3Cb%3E.txt", "z.txt"]);
+        assert_eq!(
+            order(&list("/?sort=size")),
+            ["sub%20dir/", "z.txt", "a%20%3Cb%3E.txt"]
+        );
+        assert_eq!(
+            order(&list("/?sort=name&order=desc")),
+            ["sub%20dir/", "z.txt", "a%20%3Cb%3E.txt"]
+        );
+        // unknown values fall back to the default order
+        assert_eq!(
+            order(&list("/?sort=%3Cscript%3E&order=sideways")),
+            ["sub%20dir/", "a%20%3Cb%3E.txt", "z.txt"]
+        );
+
+        let hidden = StaticFiles::new(&root).handle(
+            &mut Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..], &Default::default())
+                .unwrap(),
+        );
+        assert_eq!(hidden.status, StatusCode::NotFound);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn answers_range_requests() {
+        let path = std::env::temp_dir().join(format!("web_server_ranges_{}", std::process::id()));
+        fs::write(&path, "0123456789").unwrap();
+        let files = StaticFiles::new(std::env::temp_dir());
+        let get = |range: &str| {
+            let raw = format!("GET /f HTTP/1.1\r\nRange: {range}\r\n\r\n");
+            let request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            files.serve_file(&request, &path)
+        };
+
+        let partial = get("bytes=2-4");
+        assert_eq!(partial.status, StatusCode::PartialContent);
+        assert_eq!(partial.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(partial.body.len(), Some(3));
+
+        let beyond = get("bytes=10-");
+        assert_eq!(beyond.status, StatusCode::RangeNotSatisfiable);
+        assert_eq!(beyond.headers.get("Content-Range"), Some("bytes */10"));
+        // malformed ranges are ignored, the whole file is sent
+        for range in ["bytes=4-2", "lines=1-2", "bytes=x-"] {
+            let whole = get(range);
+            assert_eq!(whole.status, StatusCode::Ok, "{range}");
+            assert_eq!(whole.body.len(), Some(10));
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn serves_precompressed_siblings() {
+        let root = std::env::temp_dir().join(format!("web_server_gz_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("app.js");
+        fs::write(&path, "plain").unwrap();
+        fs::write(root.join("app.js.gz"), compression::gzip(b"plain")).unwrap();
+        let get = |files: &StaticFiles, accept: &str| {
+            let raw = format!("GET /app.js HTTP/1.1\r\nAccept-Encoding: {accept}\r\n\r\n");
+            let request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            files.serve_file(&request, &path)
+        };
+        let files = StaticFiles::new(&root);
+
+        let gzipped = get(&files, "gzip, deflate");
+        assert_eq!(gzipped.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(gzipped.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            gzipped.headers.get("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(
+            gzipped.body.len(),
+            Some(compression::gzip(b"plain").len() as u64)
+        );
+
+        for accept in ["identity", "gzip;q=0"] {
+            let plain = get(&files, accept);
+            assert_eq!(plain.headers.get("Content-Encoding"), None, "{accept}");
+            assert_eq!(plain.headers.get("Vary"), Some("Accept-Encoding"));
+            assert_eq!(plain.body.len(), Some(5));
+        }
+        let off = get(&files.with_precompressed(false), "gzip");
+        assert_eq!(off.headers.get("Content-Encoding"), None);
+        assert_eq!(off.headers.get("Vary"), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let files = StaticFiles::new("public");
+
+        assert_eq!(files.resolve("/../etc/passwd"), None);
+        assert_eq!(files.resolve("/a/%2e%2e/%2e%2e/etc/passwd"), None);
+        assert_eq!(files.resolve("/a%5c..%5cb"), None);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::server::Transport;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use std::{io, net::TcpStream, path::Path, sync::Arc};
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+impl Transport for TlsStream {
+    fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        // best effort, the peer may already be gone
+        let _ = self.conn.complete_io(&mut self.sock);
+    }
+}
+
+/// Wraps accepted sockets in TLS using one certificate chain and key.
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Loads a PEM certificate chain (leaf first) and a PEM private key in
+    /// PKCS#8, PKCS#1 or SEC1 form.
+    pub fn from_pem_files(cert_path: &Path, key_path: &Path) -> io::Result<TlsAcceptor> {
+        let certs = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| {
+                invalid(format!(
+                    "can't read certificates from {}: {e}",
+                    cert_path.display()
+                ))
+            })?;
+        if certs.is_empty() {
+            return Err(invalid(format!(
+                "no certificates in {}",
+                cert_path.display()
+            )));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| {
+            invalid(format!(
+                "can't read private key from {}: {e}",
+                key_path.display()
+            ))
+        })?;
+
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+                .map_err(|e| invalid(format!("invalid certificate or key: {e}")))?;
+
+        Ok(TlsAcceptor {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Starts a TLS session on `stream`; the handshake happens on first use.
+    pub fn accept(&self, stream: TcpStream) -> io::Result<TlsStream> {
+        let connection = ServerConnection::new(Arc::clone(&self.config))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,140 @@
+use crate::{
+    request::{Request, Version},
+    response::{Response, StatusCode},
+    router::Handler,
+};
+use std::sync::Arc;
+
+/// Picks a site by the `Host` header.
+///
+/// Names are matched without the port and case-insensitively. A name
+/// starting with `*.` matches every subdomain below it (`*.example.com`
+/// matches `a.example.com` and `a.b.example.com`, not `example.com`); exact
+/// names win over wildcards and longer wildcards over shorter ones. Requests
+/// for any other host, and HTTP/1.0 requests without `Host`, go to the
+/// default site.
+///
+/// HTTP/1.1 requires exactly one `Host` header, so requests with none or
+/// several are answered with 400.
+pub struct VirtualHosts {
+    hosts: Vec<(String, Arc<dyn Handler>)>,
+    wildcards: Vec<(String, Arc<dyn Handler>)>,
+    default: Arc<dyn Handler>,
+}
+
+impl VirtualHosts {
+    pub fn new<H: Handler + 'static>(default: H) -> VirtualHosts {
+        VirtualHosts {
+            hosts: Vec::new(),
+            wildcards: Vec::new(),
+            default: Arc::new(default),
+        }
+    }
+
+    /// Serves requests for `name`, e.g. `example.com` or `*.example.com`,
+    /// with `handler`.
+    pub fn host<H: Handler + 'static>(mut self, name: &str, handler: H) -> VirtualHosts {
+        let name = normalize(name);
+        match name.strip_prefix("*.") {
+            Some(domain) => {
+                self.wildcards
+                    .push((format!(".{domain}"), Arc::new(handler)));
+                // longest suffix first, so the most specific wildcard wins
+                self.wildcards
+                    .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+            }
+            None => self.hosts.push((name, Arc::new(handler))),
+        }
+        self
+    }
+
+    fn site(&self, host: &str) -> &dyn Handler {
+        let host = normalize(host);
+        if let Some((_, handler)) = self.hosts.iter().find(|(name, _)| *name == host) {
+            return handler.as_ref();
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+            .map_or(self.default.as_ref(), |(_, handler)| handler.as_ref())
+    }
+
+    /// The site `request` is for; `None` without exactly one `Host`, which
+    /// only HTTP/1.0 clients may leave out.
+    fn site_for(&self, request: &Request) -> Option<&dyn Handler> {
+        let hosts: Vec<&str> = request.headers.get_all("Host").collect();
+        match hosts.as_slice() {
+            [host] if !host.trim().is_empty() => Some(self.site(host)),
+            [] if request.version == Version::Http10 => Some(self.default.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &mut Request) -> Response {
+        match self.site_for(request) {
+            Some(site) => site.handle(request),
+            None => Response::error(StatusCode::BadRequest),
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.site_for(request)
+            .is_some_and(|site| site.streams_body(request))
+    }
+}
+
+/// Lowercases `host` and drops the port and a trailing dot.
+fn normalize(host: &str) -> String {
+    let host = host.trim();
+    let name = if host.starts_with('[') {
+        // an IPv6 literal, the port comes after the closing bracket
+        host.split_inclusive(']').next().unwrap_or(host)
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(head: &str) -> Request {
+        let raw = format!("GET / {head}\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap()
+    }
+
+    fn site(name: &'static str) -> impl Fn(&mut Request) -> Response {
+        move |_: &mut Request| Response::text(name)
+    }
+
+    #[test]
+    fn routes_by_host() {
+        let hosts = VirtualHosts::new(site("default"))
+            .host("example.com", site("example"))
+            .host("*.example.com", site("any"))
+            .host("*.api.example.com", site("api"))
+            .host("[::1]", site("ipv6"));
+
+        let body = |head| {
+            let response = hosts.handle(&mut request(head));
+            String::from_utf8(response.body.as_bytes().unwrap().to_vec()).unwrap()
+        };
+        assert_eq!(body("HTTP/1.1\r\nHost: Example.COM:8080"), "example");
+        assert_eq!(body("HTTP/1.1\r\nHost: example.com."), "example");
+        assert_eq!(body("HTTP/1.1\r\nHost: www.example.com"), "any");
+        assert_eq!(body("HTTP/1.1\r\nHost: v1.api.example.com"), "api");
+        assert_eq!(body("HTTP/1.1\r\nHost: [::1]:7878"), "ipv6");
+        assert_eq!(body("HTTP/1.1\r\nHost: notexample.com"), "default");
+        assert_eq!(body("HTTP/1.0"), "default");
+
+        let status = |head| hosts.handle(&mut request(head)).status;
+        assert_eq!(status("HTTP/1.1"), StatusCode::BadRequest);
+        assert_eq!(
+            status("HTTP/1.1\r\nHost: a.com\r\nHost: b.com"),
+            StatusCode::BadRequest
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{
+    base64,
+    request::{Method, Request, Version},
+    response::{Response, StatusCode},
+    server::{Upgraded, POLL_INTERVAL},
+    sha1::sha1,
+};
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// Appended to the client's key before hashing, fixed by RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// How long the rest of a frame may take once its first byte arrived.
+const FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for the client to answer our close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Close codes from RFC 6455 section 7.4.1.
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// The `Sec-WebSocket-Accept` value proving we understood the handshake.
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// Answers a WebSocket handshake. Once the `101` response is out, `handler`
+/// runs on the worker thread with the open socket; the connection is closed
+/// when it returns.
+///
+/// ```no_run
+/// use web_server_final_project::{request::Request, websocket::{self, Message}};
+///
+/// let echo = |req: &mut Request| {
+///     websocket::upgrade(req, |ws| {
+///         while let Ok(Some(message)) = ws.recv() {
+///             if ws.send(&message).is_err() {
+///                 break;
+///             }
+///         }
+///     })
+/// };
+/// ```
+pub fn upgrade<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce(&mut WebSocket) + Send + 'static,
+{
+    if request.method != Method::Get || request.version != Version::Http11 {
+        return Response::error(StatusCode::BadRequest);
+    }
+    let upgrade_required = || {
+        Response::error(StatusCode::UpgradeRequired)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+    };
+    if !request.headers.has_token("Upgrade", "websocket")
+        || !request.headers.has_token("Connection", "upgrade")
+    {
+        return upgrade_required();
+    }
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return upgrade_required().with_header("Sec-WebSocket-Version", "13");
+    }
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) if base64::decode(key).is_some_and(|nonce| nonce.len() == 16) => key,
+        _ => return Response::error(StatusCode::BadRequest),
+    };
+
+    Response::new(StatusCode::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", &accept_key(key))
+        .with_upgrade(move |io| {
+            let mut socket = WebSocket {
+                io,
+                partial: None,
+                closed: false,
+                max_message_len: 16 * 1024 * 1024,
+            };
+            handler(&mut socket);
+            let _ = socket.close(NORMAL_CLOSURE, "");
+        })
+}
+
+/// An open WebSocket connection. Pings are answered and the close handshake
+/// is done while receiving; a server shutdown closes it with 1001.
+pub struct WebSocket<'a> {
+    io: Upgraded<'a>,
+    /// Opcode and payload of a fragmented message still arriving.
+    partial: Option<(u8, Vec<u8>)>,
+    /// Whether a close frame has been sent.
+    closed: bool,
+    max_message_len: usize,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl WebSocket<'_> {
+    /// Messages longer than this, all fragments together, close the
+    /// connection with 1009. 16 MiB by default.
+    pub fn set_max_message_len(&mut self, len: usize) {
+        self.max_message_len = len;
+    }
+
+    /// Waits for the next message. Returns `None` once the connection is
+    /// closed, by either side or by a server shutdown.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        self.receive(None)
+    }
+
+    /// Like `recv`, but gives up with a `TimedOut` error after `timeout` so
+    /// the caller can send something meanwhile, e.g. a dashboard update.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> io::Result<Option<Message>> {
+        self.receive(Some(Instant::now() + timeout))
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_text(text),
+            Message::Binary(data) => self.send_binary(data),
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> io::Result<()> {
+        self.write_frame(TEXT, text.as_bytes())
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_frame(BINARY, data)
+    }
+
+    /// Sends a ping; the pong arrives (and is dropped) during `recv`.
+    pub fn ping(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_frame(PING, data)
+    }
+
+    /// Starts the close handshake and waits briefly for the client's answer.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        let mut payload = code.to_be_bytes().to_vec();
+        // control frames carry at most 125 bytes
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write_frame(CLOSE, &payload)?;
+        self.closed = true;
+
+        // whatever else the client sends until its close frame is dropped
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.io.wait_for_data(remaining)? {
+                return Ok(());
+            }
+            self.io.set_read_timeout(remaining);
+            if self.read_frame()?.opcode == CLOSE {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn receive(&mut self, deadline: Option<Instant>) -> io::Result<Option<Message>> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            if self.io.is_shutting_down() {
+                self.close(GOING_AWAY, "server shutting down")?;
+                return Ok(None);
+            }
+            // wake up regularly to notice a shutdown
+            let wait = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    remaining.min(POLL_INTERVAL)
+                }
+                None => POLL_INTERVAL,
+            };
+            if !self.io.wait_for_data(wait)? {
+                continue;
+            }
+
+            self.io.set_read_timeout(FRAME_TIMEOUT);
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                PING => self.write_frame(PONG, &frame.payload)?,
+                PONG => {}
+                CLOSE => {
+                    let code = &frame.payload[..frame.payload.len().min(2)];
+                    if let Err((code, reason)) = check_close(&frame.payload) {
+                        return Err(self.fail(code, reason));
+                    }
+                    // echo the status code, that completes the close handshake
+                    self.write_frame(CLOSE, code)?;
+                    self.closed = true;
+                    return Ok(None);
+                }
+                TEXT | BINARY if self.partial.is_some() => {
+                    return Err(self.fail(PROTOCOL_ERROR, "expected a continuation frame"));
+                }
+                TEXT | BINARY if frame.fin => {
+                    return self.message(frame.opcode, frame.payload).map(Some);
+                }
+                TEXT | BINARY => self.partial = Some((frame.opcode, frame.payload)),
+                CONTINUATION => {
+                    let Some((opcode, mut data)) = self.partial.take() else {
+                        return Err(self.fail(PROTOCOL_ERROR, "unexpected continuation frame"));
+                    };
+                    data.extend_from_slice(&frame.payload);
+                    if data.len() > self.max_message_len {
+                        return Err(self.fail(MESSAGE_TOO_BIG, "message too big"));
+                    }
+                    if frame.fin {
+                        return self.message(opcode, data).map(Some);
+                    }
+                    self.partial = Some((opcode, data));
+                }
+                _ => return Err(self.fail(PROTOCOL_ERROR, "unknown opcode")),
+            }
+        }
+    }
+
+    fn message(&mut self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+        if opcode == BINARY {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(INVALID_DATA, "text message is not UTF-8")),
+        }
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut head = [0; 2];
+        self.io.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        let masked = head[1] & 0x80 != 0;
+
+        // the reserved bits are for extensions, and we negotiated none
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(PROTOCOL_ERROR, "reserved bits set"));
+        }
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.io.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.io.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        if opcode >= CLOSE && (!fin || len > 125) {
+            return Err(self.fail(PROTOCOL_ERROR, "bad control frame"));
+        }
+        if !masked {
+            return Err(self.fail(PROTOCOL_ERROR, "client frames must be masked"));
+        }
+        if len > self.max_message_len as u64 {
+            return Err(self.fail(MESSAGE_TOO_BIG, "message too big"));
+        }
+
+        let mut mask = [0; 4];
+        self.io.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        self.io.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    /// Server frames are never masked or fragmented.
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the WebSocket is closed",
+            ));
+        }
+        let mut head = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => head.push(len as u8),
+            len @ 126..=0xffff => {
+                head.push(126);
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                head.push(127);
+                head.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        self.io.write_all(&head)?;
+        self.io.write_all(payload)?;
+        self.io.flush()
+    }
+
+    /// Closes the connection after a protocol violation by the client.
+    fn fail(&mut self, code: u16, reason: &str) -> io::Error {
+        if !self.closed {
+            let mut payload = code.to_be_bytes().to_vec();
+            payload.extend_from_slice(reason.as_bytes());
+            let _ = self.write_frame(CLOSE, &payload);
+            self.closed = true;
+        }
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+}
+
+/// Checks a close frame's payload: empty, or a code a peer may send
+/// followed by a UTF-8 reason, see RFC 6455 sections 5.5.1 and 7.4.
+fn check_close(payload: &[u8]) -> Result<(), (u16, &'static str)> {
+    let (code, reason) = match payload {
+        [] => return Ok(()),
+        [_] => return Err((PROTOCOL_ERROR, "close frame with a truncated code")),
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+    };
+    // 1004 to 1006 and 1015 are reserved, 1016 to 2999 not assigned yet
+    if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+        return Err((PROTOCOL_ERROR, "invalid close code"));
+    }
+    if std::str::from_utf8(reason).is_err() {
+        return Err((INVALID_DATA, "close reason is not UTF-8"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_accept_key() {
+        // the example from RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn rejects_plain_requests() {
+        let raw = "GET /ws HTTP/1.1\r\nHost: x\r\n\r\n";
+        let request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+
+        let response = upgrade(&request, |_| {});
+        assert_eq!(response.status, StatusCode::UpgradeRequired);
+        assert_eq!(response.headers.get("Upgrade"), Some("websocket"));
+    }
+
+    #[test]
+    fn rejects_bad_handshakes() {
+        let handshake = |request_line: &str, version: &str, key: &str| {
+            let raw = format!(
+                "{request_line}\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                 Sec-WebSocket-Version: {version}\r\nSec-WebSocket-Key: {key}\r\n\r\n"
+            );
+            let request = Request::read_from(&mut raw.as_bytes(), &Default::default()).unwrap();
+            upgrade(&request, |_| {})
+        };
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+
+        let ok = handshake("GET /ws HTTP/1.1", "13", key);
+        assert_eq!(ok.status, StatusCode::SwitchingProtocols);
+
+        let old_version = handshake("GET /ws HTTP/1.1", "8", key);
+        assert_eq!(old_version.status, StatusCode::UpgradeRequired);
+        assert_eq!(old_version.headers.get("Sec-WebSocket-Version"), Some("13"));
+
+        // the key has to be 16 bytes in base64
+        for key in ["", "not base64!", "c2hvcnQ="] {
+            let response = handshake("GET /ws HTTP/1.1", "13", key);
+            assert_eq!(response.status, StatusCode::BadRequest, "{key}");
+        }
+        for request_line in ["POST /ws HTTP/1.1", "GET /ws HTTP/1.0"] {
+            let response = handshake(request_line, "13", key);
+            assert_eq!(response.status, StatusCode::BadRequest, "{request_line}");
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use web_server_final_project::{client::Client, request::Method, response::StatusCode};
+
+/// The server binary on an ephemeral port; killed when dropped.
+struct Server {
+    child: Child,
+    url: String,
+}
+
+impl Server {
+    fn start() -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_web_server_final_project"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .args(["--bind", "127.0.0.1:0", "--root", "public"])
+            .args(["--access-log", "off", "--workers", "4"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+        let url = lines
+            .by_ref()
+            .map_while(Result::ok)
+            .find_map(|line| {
+                let at = line.find("listening on http://")?;
+                Some(line[at + "listening on ".len()..].trim().to_string())
+            })
+            .expect("the server didn't say where it listens");
+        // keep draining the log so the server never blocks on a full pipe
+        thread::spawn(move || lines.for_each(drop));
+        Server { child, url }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn serves_pages_concurrently() {
+    let server = Server::start();
+    let client = Client::new().with_timeout(Duration::from_secs(10));
+
+    let started = Instant::now();
+    let (sleepy, missing) = thread::scope(|s| {
+        let sleepy = s.spawn(|| client.get(&format!("{}/sleep", server.url)).send());
+        // give /sleep a head start so it is busy while the others are served
+        thread::sleep(Duration::from_millis(200));
+        let home = client.get(&format!("{}/", server.url)).send().unwrap();
+        assert_eq!(home.status, StatusCode::Ok);
+        assert!(home.text().contains("Hello!"), "{}", home.text());
+        assert!(
+            started.elapsed() < Duration::from_secs(3),
+            "/ waited for /sleep"
+        );
+
+        let missing = s.spawn(|| client.get(&format!("{}/nope.html", server.url)).send());
+        (sleepy.join().unwrap(), missing.join().unwrap())
+    });
+
+    let sleepy = sleepy.unwrap();
+    assert_eq!(sleepy.status, StatusCode::Ok);
+    assert!(started.elapsed() >= Duration::from_secs(5));
+    let missing = missing.unwrap();
+    assert_eq!(missing.status, StatusCode::NotFound);
+    assert!(missing.text().contains("Oops!"), "{}", missing.text());
+}
+
+#[test]
+fn reuses_kept_alive_connections() {
+    // accepts a single connection, so a second request only gets an answer
+    // if it comes over the same one
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let backend = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        for body in ["first", "second"] {
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            writer.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let client = Client::new().with_timeout(Duration::from_secs(5));
+    assert_eq!(client.get(&url).send().unwrap().text(), "first");
+    assert_eq!(
+        client.get(&format!("{url}/again")).send().unwrap().text(),
+        "second"
+    );
+    backend.join().unwrap();
+}
+
+#[test]
+fn retries_only_idempotent_requests_on_stale_connections() {
+    // closes the first connection after one answer, then says which request
+    // came over the second
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let backend = thread::spawn(move || {
+        let mut request_lines = Vec::new();
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut writer = stream;
+            writer
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request_lines.push(request_line);
+        }
+        request_lines
+    });
+
+    let client = Client::new().with_timeout(Duration::from_secs(5));
+    client.get(&url).send().unwrap();
+    // let the close reach the client before the connection is reused
+    thread::sleep(Duration::from_millis(100));
+    assert!(client.post(&format!("{url}/order")).send().is_err());
+    assert_eq!(
+        client.get(&format!("{url}/again")).send().unwrap().status,
+        StatusCode::Ok
+    );
+    assert_eq!(
+        backend.join().unwrap(),
+        ["GET / HTTP/1.1\r\n", "GET /again HTTP/1.1\r\n"]
+    );
+}
+
+#[test]
+fn sends_head_and_closing_requests() {
+    let server = Server::start();
+    let client = Client::new().with_timeout(Duration::from_secs(10));
+
+    for _ in 0..3 {
+        let response = client.get(&format!("{}/", server.url)).send().unwrap();
+        assert_eq!(response.status, StatusCode::Ok);
+    }
+    let head = client
+        .request(Method::Head, &format!("{}/", server.url))
+        .send()
+        .unwrap();
+    assert_eq!(head.status, StatusCode::Ok);
+    assert!(head.body.is_empty());
+    let response = client
+        .get(&format!("{}/", server.url))
+        .header("Connection", "close")
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::Ok);
+    assert_eq!(response.header("Connection"), Some("close"));
+}
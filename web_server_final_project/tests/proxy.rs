@@ -0,0 +1,254 @@
+use std::{
+    io::{self, Read, Write},
+    net::{self, SocketAddr, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use web_server_final_project::{
+    client::Client,
+    compression::Compression,
+    middleware::Chain,
+    proxy::Proxy,
+    request::{Limits, Request},
+    response::Response,
+    router::{Handler, Router},
+    server::{ConnectionSettings, Server},
+    shutdown::Shutdown,
+};
+
+fn start<H: Handler + 'static>(
+    handler: H,
+    shutdown: &Shutdown,
+) -> (SocketAddr, JoinHandle<io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(handler, 2)
+        .listener(listener)
+        .shutdown(shutdown.clone());
+    (addr, thread::spawn(move || server.run()))
+}
+
+fn send(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn forwards_to_a_backend_server() {
+    let shutdown = Shutdown::new();
+    let backend = Router::new()
+        .post("/echo", |req: &mut Request| {
+            let seen = format!(
+                "{} {} for={} host={} secret={}",
+                req.target(),
+                String::from_utf8_lossy(&req.body),
+                req.header("X-Forwarded-For").unwrap_or("-"),
+                req.header("X-Forwarded-Host").unwrap_or("-"),
+                req.header("X-Secret").unwrap_or("-"),
+            );
+            Response::text(seen).with_header("Keep-Alive", "timeout=5")
+        })
+        .get("/stream", |_: &mut Request| {
+            Response::text("").with_reader(&b"streamed body"[..], None)
+        });
+    let (backend_addr, backend) = start(backend, &shutdown);
+
+    // nothing listens on this one, so the proxy has to fall back to the backend
+    let dead_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let proxy = Proxy::new([dead_addr.to_string(), backend_addr.to_string()]);
+    let (proxy_addr, front) = start(proxy, &shutdown);
+
+    for _ in 0..3 {
+        let response = send(
+            proxy_addr,
+            "POST /echo?x=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close, X-Secret\r\n\
+             X-Secret: 1\r\nX-Forwarded-For: 10.0.0.1\r\nContent-Length: 5\r\n\r\nhello",
+        );
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert!(!head.contains("Keep-Alive"));
+        assert_eq!(
+            body,
+            "/echo?x=1 hello for=10.0.0.1, 127.0.0.1 host=example.com secret=-"
+        );
+    }
+
+    // the backend sends this chunked, the proxy passes it on as it arrives
+    let response = send(
+        proxy_addr,
+        "GET /stream HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+    );
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Transfer-Encoding: chunked"), "{head}");
+    assert_eq!(body, "d\r\nstreamed body\r\n0\r\n\r\n");
+
+    shutdown.trigger();
+    front.join().unwrap().unwrap();
+    backend.join().unwrap().unwrap();
+}
+
+#[test]
+fn answers_502_without_a_reachable_upstream() {
+    let shutdown = Shutdown::new();
+    let dead_addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let (proxy_addr, front) = start(Proxy::new([dead_addr.to_string()]), &shutdown);
+
+    let response = send(
+        proxy_addr,
+        "GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+    );
+    assert!(
+        response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"),
+        "{response}"
+    );
+
+    shutdown.trigger();
+    front.join().unwrap().unwrap();
+}
+
+#[test]
+fn passes_head_answers_on_untouched() {
+    let shutdown = Shutdown::new();
+    let page = "<p>compressible</p>".repeat(200);
+    let backend = Router::new().get("/page", move |_: &mut Request| Response::html(page.clone()));
+    let (backend_addr, backend) = start(backend, &shutdown);
+    let proxy = Chain::new(Proxy::new([backend_addr.to_string()])).with(Compression {
+        min_size: 0,
+        ..Compression::default()
+    });
+    let (proxy_addr, front) = start(proxy, &shutdown);
+
+    let head = send(
+        proxy_addr,
+        "HEAD /page HTTP/1.1\r\nHost: a\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+    );
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Content-Length: 3800\r\n"), "{head}");
+    assert!(!head.contains("Content-Encoding"), "{head}");
+    assert!(head.ends_with("\r\n\r\n"), "{head}");
+
+    // a GET through the same chain is compressed
+    let get = Client::new()
+        .get(&format!("http://{proxy_addr}/page"))
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .unwrap();
+    assert_eq!(get.header("Content-Encoding"), Some("gzip"));
+    assert!(get.body.len() < 3800);
+
+    shutdown.trigger();
+    front.join().unwrap().unwrap();
+    backend.join().unwrap().unwrap();
+}
+
+#[test]
+fn answers_502_and_504_for_broken_upstreams() {
+    let shutdown = Shutdown::new();
+    // accepts connections but answers with garbage, or not at all
+    let upstream = |reply: &'static [u8]| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(1) {
+                let mut stream = stream.unwrap();
+                stream.write_all(reply).unwrap();
+                thread::sleep(Duration::from_millis(500));
+            }
+        });
+        addr
+    };
+    let garbage = upstream(b"SPDY/3 what\r\n\r\n");
+    let silent = upstream(b"");
+    let router = Router::new()
+        .get("/garbage", Proxy::new([garbage.to_string()]))
+        .get(
+            "/silent",
+            Proxy::new([silent.to_string()]).with_timeout(Duration::from_millis(100)),
+        );
+    let (proxy_addr, front) = start(router, &shutdown);
+
+    let get = |path: &str| {
+        send(
+            proxy_addr,
+            &format!("GET {path} HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n"),
+        )
+    };
+    let response = get("/garbage");
+    assert!(response.starts_with("HTTP/1.1 502 "), "{response}");
+    let response = get("/silent");
+    assert!(response.starts_with("HTTP/1.1 504 "), "{response}");
+
+    shutdown.trigger();
+    front.join().unwrap().unwrap();
+}
+
+#[test]
+fn streams_request_bodies_to_the_upstream() {
+    let shutdown = Shutdown::new();
+    let backend = Router::new().post("/echo", |req: &mut Request| {
+        let framing = match req.header("Content-Length") {
+            Some(length) => format!("length={length}"),
+            None => format!("te={}", req.header("Transfer-Encoding").unwrap_or("-")),
+        };
+        Response::text(format!("{framing} {}", String::from_utf8_lossy(&req.body)))
+    });
+    let (backend_addr, backend) = start(backend, &shutdown);
+    // the proxy's own body limit doesn't apply to what it passes on
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let settings = ConnectionSettings {
+        limits: Limits {
+            max_body_len: 4,
+            ..Limits::default()
+        },
+        ..ConnectionSettings::default()
+    };
+    let server = Server::new(Proxy::new([backend_addr.to_string()]), 2)
+        .listener(listener)
+        .settings(settings)
+        .shutdown(shutdown.clone());
+    let front = thread::spawn(move || server.run());
+
+    let response = send(
+        proxy_addr,
+        "POST /echo HTTP/1.1\r\nHost: a\r\nConnection: close\r\nContent-Length: 11\r\n\r\n\
+         hello world",
+    );
+    assert!(
+        response.ends_with("\r\n\r\nlength=11 hello world"),
+        "{response}"
+    );
+
+    let response = send(
+        proxy_addr,
+        "POST /echo HTTP/1.1\r\nHost: a\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n\
+         6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n",
+    );
+    assert!(
+        response.ends_with("\r\n\r\nte=chunked hello world"),
+        "{response}"
+    );
+
+    // a body that stops short is the client's fault, not the upstream's
+    let mut stream = TcpStream::connect(proxy_addr).unwrap();
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello")
+        .unwrap();
+    stream.shutdown(net::Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+
+    shutdown.trigger();
+    front.join().unwrap().unwrap();
+    backend.join().unwrap().unwrap();
+}
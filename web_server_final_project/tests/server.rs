@@ -0,0 +1,280 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use web_server_final_project::{
+    form::{MultipartLimits, PartData},
+    request::{Limits, Request},
+    response::Response,
+    router::{Handler, Router, Streaming},
+    server::{ConnectionSettings, Server},
+    shutdown::Shutdown,
+};
+
+fn start<H: Handler + 'static>(
+    handler: H,
+    settings: ConnectionSettings,
+    configure: impl FnOnce(Server) -> Server,
+) -> (SocketAddr, Shutdown, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let server = configure(
+        Server::new(handler, 1)
+            .listener(listener)
+            .settings(settings)
+            .shutdown(shutdown.clone()),
+    );
+    (addr, shutdown, thread::spawn(move || server.run().unwrap()))
+}
+
+fn hello() -> Router {
+    Router::new().get("/", |_: &mut Request| Response::text("hello"))
+}
+
+/// Reads one response with a `Content-Length` body; returns the head and
+/// body, or `None` if the connection was closed before a response.
+fn read_response(reader: &mut BufReader<TcpStream>) -> Option<(String, String)> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        if line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let length = head
+        .lines()
+        .find_map(|l| l.strip_prefix("Content-Length: "))
+        .map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some((head, String::from_utf8(body).unwrap()))
+}
+
+fn closed(reader: &mut BufReader<TcpStream>) -> bool {
+    matches!(reader.read(&mut [0; 1]), Ok(0))
+}
+
+fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+#[test]
+fn serves_requests_until_the_cap() {
+    let settings = ConnectionSettings {
+        max_requests: 3,
+        ..ConnectionSettings::default()
+    };
+    let (addr, shutdown, server) = start(hello(), settings, |s| s);
+    let (mut stream, mut reader) = connect(addr);
+
+    // pipelined: all three are sent before any answer is read
+    for _ in 0..3 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+    }
+    for served in 1..=3 {
+        let (head, body) = read_response(&mut reader).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert_eq!(body, "hello");
+        let connection = if served < 3 { "keep-alive" } else { "close" };
+        assert!(
+            head.contains(&format!("Connection: {connection}\r\n")),
+            "{head}"
+        );
+    }
+    assert!(closed(&mut reader));
+
+    shutdown.trigger();
+    server.join().unwrap();
+}
+
+#[test]
+fn closes_when_asked_or_idle() {
+    let settings = ConnectionSettings {
+        idle_timeout: Duration::from_millis(300),
+        ..ConnectionSettings::default()
+    };
+    let (addr, shutdown, server) = start(hello(), settings, |s| s);
+
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let (head, _) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: close\r\n"), "{head}");
+    // the second request was never answered
+    assert!(closed(&mut reader));
+
+    // HTTP/1.0 closes unless the client asks to keep the connection
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let (head, _) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: close\r\n"), "{head}");
+    assert!(closed(&mut reader));
+
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let (head, _) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: keep-alive\r\n"), "{head}");
+    let idle = Instant::now();
+    assert!(closed(&mut reader));
+    assert!(idle.elapsed() < Duration::from_secs(3));
+
+    // a request that stops halfway runs into the request timeout
+    let settings = ConnectionSettings {
+        request_timeout: Duration::from_millis(300),
+        ..ConnectionSettings::default()
+    };
+    let (slow_addr, slow_shutdown, slow_server) = start(hello(), settings, |s| s);
+    let (mut stream, mut reader) = connect(slow_addr);
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+    let stalled = Instant::now();
+    let (head, _) = read_response(&mut reader).unwrap();
+    assert!(head.starts_with("HTTP/1.1 408 "), "{head}");
+    assert!(closed(&mut reader));
+    assert!(stalled.elapsed() < Duration::from_secs(3));
+
+    for (shutdown, server) in [(shutdown, server), (slow_shutdown, slow_server)] {
+        shutdown.trigger();
+        server.join().unwrap();
+    }
+}
+
+#[test]
+fn streams_bodies_to_handlers_that_ask() {
+    let router = Router::new()
+        .post("/buffered", |req: &mut Request| {
+            Response::text(req.body.len().to_string())
+        })
+        .post(
+            "/streamed",
+            Streaming(|req: &mut Request| {
+                let body = req.body_reader().unwrap();
+                let length = body.content_length();
+                let mut received = Vec::new();
+                body.read_to_end(&mut received).unwrap();
+                Response::text(format!("{length:?} {}", received.len()))
+            }),
+        )
+        .post(
+            "/ignored",
+            Streaming(|_: &mut Request| Response::text("no thanks")),
+        )
+        .post(
+            "/upload",
+            Streaming(|req: &mut Request| {
+                let limits = MultipartLimits {
+                    memory_threshold: 4,
+                    ..MultipartLimits::default()
+                };
+                let form = req.multipart(&limits).unwrap();
+                let file = form.part("file").unwrap();
+                let on_disk = matches!(file.data, PartData::File(_));
+                let bytes = file.bytes().unwrap();
+                Response::text(format!("{on_disk} {}", String::from_utf8(bytes).unwrap()))
+            }),
+        );
+    let settings = ConnectionSettings {
+        limits: Limits {
+            max_body_len: 8,
+            ..Limits::default()
+        },
+        ..ConnectionSettings::default()
+    };
+    let (addr, shutdown, server) = start(router, settings, |s| s);
+    let (mut stream, mut reader) = connect(addr);
+
+    // past the body limit, which only applies to bodies read up front
+    let chunks = "POST /streamed HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  a\r\n0123456789\r\n5\r\nabcde\r\n0\r\n\r\n";
+    stream.write_all(chunks.as_bytes()).unwrap();
+    let (head, body) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: keep-alive\r\n"), "{head}");
+    assert_eq!(body, "None 15");
+    stream
+        .write_all(b"POST /streamed HTTP/1.1\r\nContent-Length: 12\r\n\r\nhello, world")
+        .unwrap();
+    assert_eq!(read_response(&mut reader).unwrap().1, "Some(12) 12");
+    let form = "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n\
+                hello, world\r\n--b--\r\n";
+    let upload = format!(
+        "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+         Content-Length: {}\r\n\r\n{form}",
+        form.len()
+    );
+    stream.write_all(upload.as_bytes()).unwrap();
+    let (head, body) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: keep-alive\r\n"), "{head}");
+    assert_eq!(body, "true hello, world");
+    stream
+        .write_all(b"POST /buffered HTTP/1.1\r\nContent-Length: 12\r\n\r\nhello, world")
+        .unwrap();
+    let (head, _) = read_response(&mut reader).unwrap();
+    assert!(head.starts_with("HTTP/1.1 413 "), "{head}");
+    assert!(closed(&mut reader));
+
+    // a body the handler left unread can't be told apart from the next request
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(b"POST /ignored HTTP/1.1\r\nContent-Length: 5\r\n\r\nGET /")
+        .unwrap();
+    let (head, body) = read_response(&mut reader).unwrap();
+    assert!(head.contains("Connection: close\r\n"), "{head}");
+    assert_eq!(body, "no thanks");
+    assert!(closed(&mut reader));
+
+    shutdown.trigger();
+    server.join().unwrap();
+}
+
+#[test]
+fn survives_panicking_handlers() {
+    let router = hello().get("/panic", |_: &mut Request| -> Response { panic!("boom") });
+    // one worker and one connection per IP: both have to be given back
+    let (addr, shutdown, server) = start(router, ConnectionSettings::default(), |s| {
+        s.max_connections_per_ip(1)
+    });
+
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET /panic HTTP/1.1\r\n\r\n").unwrap();
+    assert!(read_response(&mut reader).is_none());
+
+    // the socket closes while the panic unwinds, just before the connection
+    // is given back, so the first retry may still be turned away
+    let panicked = Instant::now();
+    let mut served = 0;
+    while served < 2 {
+        let (mut stream, mut reader) = connect(addr);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let (head, body) = read_response(&mut reader).unwrap();
+        if head.starts_with("HTTP/1.1 429 ") && served == 0 {
+            assert!(panicked.elapsed() < Duration::from_secs(1), "still counted");
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert_eq!(body, "hello");
+        served += 1;
+    }
+
+    // nothing is left to drain, so this doesn't wait for the grace period
+    let stopping = Instant::now();
+    shutdown.trigger();
+    server.join().unwrap();
+    assert!(stopping.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,100 @@
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Starts the server binary on an ephemeral port and returns it with the
+/// address it listens on.
+fn start(grace_period: &str) -> (Child, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_web_server_final_project"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--bind", "127.0.0.1:0", "--root", "public"])
+        .args(["--access-log", "off", "--grace-period", grace_period])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stderr.take().unwrap()).lines();
+    let addr = lines
+        .by_ref()
+        .map_while(Result::ok)
+        .find_map(|line| {
+            let at = line.find("listening on http://")?;
+            Some(line[at + "listening on http://".len()..].trim().to_string())
+        })
+        .expect("the server didn't say where it listens");
+    thread::spawn(move || lines.for_each(drop));
+    (child, addr)
+}
+
+/// Sends `GET /sleep` and reads until the server closes the connection.
+fn sleep_request(addr: &str) -> thread::JoinHandle<String> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::spawn(move || {
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).into_owned()
+    })
+}
+
+fn terminate(child: &Child) {
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn wait(child: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    None
+}
+
+#[test]
+fn finishes_in_flight_requests_on_sigterm() {
+    let (mut child, addr) = start("30");
+    let started = Instant::now();
+    let response = sleep_request(&addr);
+    thread::sleep(Duration::from_millis(300));
+    terminate(&child);
+
+    // the listener goes away at once, in-flight requests are still answered
+    thread::sleep(Duration::from_millis(300));
+    assert!(TcpStream::connect(&addr).is_err());
+    let response = response.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Connection: close\r\n"), "{response}");
+
+    let status = wait(&mut child, Duration::from_secs(10)).expect("the server didn't exit");
+    assert!(status.success(), "{status}");
+    assert!(started.elapsed() >= Duration::from_secs(5));
+}
+
+#[test]
+fn closes_connections_left_after_the_grace_period() {
+    let (mut child, addr) = start("1");
+    let response = sleep_request(&addr);
+    thread::sleep(Duration::from_millis(300));
+    let terminated = Instant::now();
+    terminate(&child);
+
+    // /sleep takes five seconds, the server waits only one for it
+    let status = wait(&mut child, Duration::from_secs(4)).expect("the server didn't exit");
+    assert!(status.success(), "{status}");
+    assert!(terminated.elapsed() < Duration::from_secs(4));
+    assert_eq!(response.join().unwrap(), "");
+}
@@ -0,0 +1,103 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+use web_server_final_project::{
+    request::Request,
+    response::Response,
+    router::Router,
+    server::Server,
+    shutdown::Shutdown,
+    sse::{self, Event},
+};
+
+#[test]
+fn streams_events_after_the_last_seen_id() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let router = Router::new().get("/progress", |req: &mut Request| {
+        sse::stream(req, |events| {
+            events.set_heartbeat(Duration::from_millis(20));
+            let start: u32 = events
+                .last_event_id()
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0);
+            for step in start + 1..=start + 2 {
+                assert!(events.wait(Duration::from_millis(50)));
+                let event = Event::new(format!("step {step}")).with_id(step.to_string());
+                events.send(&event).unwrap();
+            }
+        })
+    });
+    let server = Server::new(router, 2)
+        .listener(listener)
+        .shutdown(shutdown.clone());
+    let server = thread::spawn(move || server.run());
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /progress HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 4\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("Content-Type: text/event-stream\r\n"));
+    assert!(head.contains("\r\nConnection: close"));
+    assert!(!head.contains("Transfer-Encoding"));
+
+    assert!(body.contains(": heartbeat\n\n"), "{body}");
+    let events: Vec<&str> = body
+        .split("\n\n")
+        .filter(|e| e.starts_with("id:"))
+        .collect();
+    assert_eq!(events, ["id: 5\ndata: step 5", "id: 6\ndata: step 6"]);
+
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn a_panicking_stream_only_ends_its_own_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let router = Router::new()
+        .get("/broken", |req: &mut Request| {
+            sse::stream(req, |events| {
+                events.send(&Event::new("first")).unwrap();
+                panic!("lost the feed");
+            })
+        })
+        .get("/ok", |_: &mut Request| Response::text("still here"));
+    // a single worker, which has to survive the panic to serve /ok
+    let server = Server::new(router, 1)
+        .listener(listener)
+        .shutdown(shutdown.clone());
+    let server = thread::spawn(move || server.run());
+
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let broken = get("/broken");
+    assert!(broken.starts_with("HTTP/1.1 200 OK\r\n"), "{broken}");
+    assert!(broken.ends_with("data: first\n\n"), "{broken}");
+    let ok = get("/ok");
+    assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"), "{ok}");
+    assert!(ok.ends_with("still here"), "{ok}");
+
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+}
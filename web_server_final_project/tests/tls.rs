@@ -0,0 +1,90 @@
+#![cfg(feature = "tls")]
+
+use rustls::{
+    pki_types::{CertificateDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+};
+use web_server_final_project::{
+    request::Request, response::Response, router::Router, server::Server, shutdown::Shutdown,
+    tls::TlsAcceptor,
+};
+
+/// Writes a fresh self-signed certificate for `localhost` and its key to a
+/// temporary directory.
+fn self_signed_cert(name: &str) -> (PathBuf, PathBuf, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = std::env::temp_dir().join(format!("web_server_tls_{name}_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    fs::write(&cert_path, certified.cert.pem()).unwrap();
+    fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+    (cert_path, key_path, certified.cert.der().clone())
+}
+
+fn tls_client(cert: CertificateDer<'static>) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Arc::new(config)
+}
+
+#[test]
+fn serves_requests_over_https() {
+    let (cert_path, key_path, cert) = self_signed_cert("serves");
+    let acceptor = Arc::new(TlsAcceptor::from_pem_files(&cert_path, &key_path).unwrap());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let router = Router::new().get("/", |_: &mut Request| Response::text("secure hello"));
+    let server = Server::new(router, 2)
+        .tls_listener(listener, acceptor)
+        .shutdown(shutdown.clone());
+    let server = thread::spawn(move || server.run());
+
+    let connection =
+        ClientConnection::new(tls_client(cert), ServerName::try_from("localhost").unwrap())
+            .unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(addr).unwrap());
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nsecure hello"));
+
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+    let _ = fs::remove_dir_all(cert_path.parent().unwrap());
+}
+
+#[test]
+fn rejects_missing_key() {
+    let (cert_path, _, _) = self_signed_cert("missing_key");
+    let missing = cert_path.with_file_name("missing.pem");
+
+    let err = TlsAcceptor::from_pem_files(&cert_path, &missing)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("can't read private key"));
+    let _ = fs::remove_dir_all(cert_path.parent().unwrap());
+}
@@ -0,0 +1,162 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+use web_server_final_project::{
+    request::Request,
+    router::Router,
+    server::Server,
+    shutdown::Shutdown,
+    websocket::{self, Message},
+};
+
+/// A masked client frame, as browsers send them.
+fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![u8::from(fin) << 7 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+fn read_frame(stream: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    assert_eq!(head[1] & 0x80, 0, "server frames are unmasked");
+    let mut payload = vec![0; usize::from(head[1] & 0x7f)];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+#[test]
+fn echoes_messages_over_a_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let router = Router::new().get("/echo", |req: &mut Request| {
+        websocket::upgrade(req, |ws| {
+            while let Ok(Some(message)) = ws.recv() {
+                let reply = match message {
+                    Message::Text(text) => Message::Text(text.to_uppercase()),
+                    binary => binary,
+                };
+                ws.send(&reply).unwrap();
+            }
+        })
+    });
+    let server = Server::new(router, 2)
+        .listener(listener)
+        .shutdown(shutdown.clone());
+    let server = thread::spawn(move || server.run());
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+              Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    // a fragmented text message with a ping in between
+    stream.write_all(&client_frame(false, 0x1, b"hel")).unwrap();
+    stream
+        .write_all(&client_frame(true, 0x9, b"are you there"))
+        .unwrap();
+    stream.write_all(&client_frame(true, 0x0, b"lo")).unwrap();
+    assert_eq!(read_frame(&mut reader), (0x8a, b"are you there".to_vec()));
+    assert_eq!(read_frame(&mut reader), (0x81, b"HELLO".to_vec()));
+
+    stream
+        .write_all(&client_frame(true, 0x2, &[0, 1, 2]))
+        .unwrap();
+    assert_eq!(read_frame(&mut reader), (0x82, vec![0, 1, 2]));
+
+    stream
+        .write_all(&client_frame(true, 0x8, &1000u16.to_be_bytes()))
+        .unwrap();
+    assert_eq!(
+        read_frame(&mut reader),
+        (0x88, 1000u16.to_be_bytes().to_vec())
+    );
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn closes_on_protocol_violations() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let router = Router::new().get("/strict", |req: &mut Request| {
+        websocket::upgrade(req, |ws| {
+            ws.set_max_message_len(8);
+            while let Ok(Some(message)) = ws.recv() {
+                ws.send(&message).unwrap();
+            }
+        })
+    });
+    let server = Server::new(router, 2)
+        .listener(listener)
+        .shutdown(shutdown.clone());
+    let server = thread::spawn(move || server.run());
+
+    // each violation on its own connection, answered by a close frame with its code
+    let violations: [(Vec<u8>, u16); 12] = [
+        (client_frame(true, 0x1, &[0xff, 0xfe]), 1007),
+        (client_frame(true, 0x2, b"far too long"), 1009),
+        (client_frame(true, 0x0, b"no start"), 1002),
+        (client_frame(true, 0x3, b""), 1002),
+        // unmasked, as only servers may send them
+        (vec![0x81, 0x02, b'h', b'i'], 1002),
+        (client_frame(true, 0x8, &[0x03]), 1002),
+        // codes an endpoint must never send, or that aren't assigned
+        (client_frame(true, 0x8, &999u16.to_be_bytes()), 1002),
+        (client_frame(true, 0x8, &1005u16.to_be_bytes()), 1002),
+        (client_frame(true, 0x8, &1006u16.to_be_bytes()), 1002),
+        (client_frame(true, 0x8, &1015u16.to_be_bytes()), 1002),
+        (client_frame(true, 0x8, &2000u16.to_be_bytes()), 1002),
+        (client_frame(true, 0x8, &[0x03, 0xe8, 0xff]), 1007),
+    ];
+    for (frame, code) in violations {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /strict HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                  Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+
+        stream.write_all(&frame).unwrap();
+        let (opcode, payload) = read_frame(&mut reader);
+        assert_eq!(opcode, 0x88, "{frame:?}");
+        assert_eq!(payload[..2], code.to_be_bytes(), "{frame:?}");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    shutdown.trigger();
+    server.join().unwrap().unwrap();
+}